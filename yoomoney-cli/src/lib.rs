@@ -0,0 +1,584 @@
+use anyhow::{bail, format_err};
+use bigdecimal::*;
+use chrono::prelude::*;
+use clap::*;
+use phonenumber::*;
+use std::path::*;
+use tokio_stream::*;
+use tracing_subscriber::{prelude::*, EnvFilter};
+use yoomoney::*;
+
+mod profiles;
+
+pub use profiles::*;
+
+pub fn config_location() -> PathBuf {
+    let mut path = xdg::BaseDirectories::new().unwrap().get_config_home();
+    path.push("yandex-money-cli/config.toml");
+
+    path
+}
+
+#[derive(Debug, Parser)]
+pub struct AuthorizeData {
+    #[clap(long, env = "CLIENT_ID")]
+    pub client_id: String,
+    #[clap(long, env = "CLIENT_REDIRECT")]
+    pub client_redirect: String,
+    #[clap(short)]
+    pub do_not_store_on_disk: bool,
+}
+
+#[derive(Clone, Copy, Debug, Default, ValueEnum)]
+pub enum LogFormat {
+    /// Human-readable lines
+    #[default]
+    Text,
+    /// One JSON object per event, for log pipelines
+    Json,
+}
+
+/// How results are printed.
+#[derive(Clone, Copy, Debug, Default, Args)]
+pub struct Output {
+    /// Print amounts as received, without rounding to kopecks
+    #[clap(long, global = true)]
+    pub raw_amounts: bool,
+}
+
+impl Output {
+    #[must_use]
+    pub fn amount(&self, amount: &BigDecimal) -> String {
+        if self.raw_amounts {
+            amount.to_string()
+        } else {
+            format_amount(amount)
+        }
+    }
+}
+
+#[derive(Debug, Parser)]
+pub struct Cli<C: Subcommand> {
+    /// Format of log output
+    #[clap(long, value_enum, default_value_t, env = "LOG_FORMAT", global = true)]
+    pub log_format: LogFormat,
+    /// Stored wallet to act on, instead of the default one
+    #[clap(long, env = "YOOMONEY_PROFILE", global = true)]
+    pub profile: Option<String>,
+    #[clap(flatten)]
+    pub output: Output,
+    #[clap(subcommand)]
+    pub cmd: C,
+}
+
+pub fn init_logging(format: LogFormat) {
+    let filter = EnvFilter::from_default_env();
+    let registry = tracing_subscriber::registry().with(filter);
+    match format {
+        LogFormat::Text => registry.with(tracing_subscriber::fmt::layer()).init(),
+        LogFormat::Json => registry
+            .with(
+                tracing_subscriber::fmt::layer()
+                    .json()
+                    .with_current_span(true),
+            )
+            .init(),
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, ValueEnum)]
+pub enum ExportFormat {
+    /// Shell environment assignments
+    #[default]
+    Env,
+    Toml,
+    Json,
+}
+
+impl From<ExportFormat> for CredentialsFormat {
+    fn from(value: ExportFormat) -> Self {
+        match value {
+            ExportFormat::Env => Self::Env,
+            ExportFormat::Toml => Self::Toml,
+            ExportFormat::Json => Self::Json,
+        }
+    }
+}
+
+/// Credentials from the `YOOMONEY_TOKEN` or `TOKEN` environment variable, or else from `profile` in the config file.
+pub async fn load_credentials(profile: Option<&str>) -> Option<StoredCredentials> {
+    match std::env::var(ENV_TOKEN).or_else(|_| std::env::var("TOKEN")) {
+        Ok(token) => Some(StoredCredentials::new(token)),
+        Err(_) => {
+            let config = Config::load(config_location()).await.ok()?;
+            config.credentials(config.resolve(profile)).cloned()
+        }
+    }
+}
+
+/// Shorten a token for display, so that it can be recognized but not reused.
+#[must_use]
+pub fn redact_token(token: &str) -> String {
+    format!("{}…", token.chars().take(6).collect::<String>())
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ProfileCmd {
+    /// List stored profiles
+    List,
+    /// Store a profile with an already issued token
+    Add {
+        name: String,
+        #[clap(long, env = "TOKEN")]
+        token: String,
+    },
+    /// Forget a profile and its token
+    Remove { name: String },
+    /// Use a profile when `--profile` is not given
+    Default { name: String },
+}
+
+#[derive(Debug, Parser)]
+pub enum UnauthorizedCmd {
+    /// Authorize client
+    Login(AuthorizeData),
+    /// Manage stored wallets
+    #[clap(subcommand)]
+    Profile(ProfileCmd),
+}
+
+#[derive(Debug, Parser)]
+pub struct To {
+    #[clap(long, conflicts_with_all = &["to_email", "to_phone"])]
+    pub to_account: Option<u64>,
+    #[clap(long, conflicts_with_all = &["to_account", "to_phone"])]
+    pub to_email: Option<String>,
+    #[clap(long, conflicts_with_all = &["to_account", "to_email"])]
+    pub to_phone: Option<PhoneNumber>,
+}
+
+impl From<To> for Option<UserId> {
+    fn from(value: To) -> Self {
+        if let Some(v) = value.to_account {
+            return Some(UserId::Account(v));
+        }
+
+        if let Some(v) = value.to_email {
+            return Some(UserId::Email(v));
+        }
+
+        if let Some(v) = value.to_phone {
+            return Some(UserId::Phone(v));
+        }
+
+        None
+    }
+}
+
+#[derive(Debug, Parser)]
+pub struct Amount {
+    #[clap(long, conflicts_with = "amount_total")]
+    pub amount_net: Option<BigDecimal>,
+    #[clap(long, conflicts_with = "amount_net")]
+    pub amount_total: Option<BigDecimal>,
+}
+
+impl From<Amount> for Option<RequestAmount> {
+    fn from(value: Amount) -> Self {
+        if let Some(v) = value.amount_net {
+            return Some(RequestAmount::Net(v));
+        }
+
+        if let Some(v) = value.amount_total {
+            return Some(RequestAmount::Total(v));
+        }
+
+        None
+    }
+}
+
+#[derive(Debug, Parser)]
+#[allow(clippy::large_enum_variant)]
+pub enum AuthorizedCmd {
+    /// Reauthorize client
+    Login(AuthorizeData),
+    /// Manage stored wallets
+    #[clap(subcommand)]
+    Profile(ProfileCmd),
+    /// Revoke token
+    Revoke,
+    /// Print stored credentials to stdout, e.g. for passing them to other tools
+    Export {
+        #[clap(long, value_enum, default_value_t)]
+        format: ExportFormat,
+    },
+    /// Request transfer
+    RequestTransfer {
+        #[clap(flatten)]
+        to: To,
+        #[clap(flatten)]
+        amount: Amount,
+        #[clap(long)]
+        comment: Option<String>,
+        #[clap(long)]
+        message: Option<String>,
+        #[clap(long)]
+        label: Option<String>,
+        #[clap(long)]
+        codepro: Option<bool>,
+        #[clap(long)]
+        protection_code: Option<ProtectionCode>,
+        #[clap(long)]
+        hold_for_pickup: Option<bool>,
+        #[clap(long)]
+        expire_period: Option<u32>,
+        /// Send without asking for confirmation
+        #[clap(long)]
+        yes: bool,
+    },
+    /// Process existing payment
+    ProcessPayment {
+        #[clap(long)]
+        request_id: String,
+        #[clap(long)]
+        money_source: ProcessPaymentMoneySource,
+    },
+    /// Show operation history
+    OperationHistory {
+        #[clap(long, conflicts_with = "last_days")]
+        from: Option<DateTime<Utc>>,
+        #[clap(long, conflicts_with = "last_days")]
+        till: Option<DateTime<Utc>>,
+        /// Show operations for this many last days
+        #[clap(long)]
+        last_days: Option<u32>,
+        #[clap(long)]
+        detailed: bool,
+    },
+}
+
+/// Authorize interactively and store the token as `profile`, or as the default profile.
+pub async fn do_authorize(
+    AuthorizeData {
+        client_id,
+        client_redirect,
+        do_not_store_on_disk,
+    }: AuthorizeData,
+    profile: Option<&str>,
+) -> anyhow::Result<()> {
+    let client = UnauthorizedClient::try_new(client_id, client_redirect)?;
+    let path = config_location();
+    let mut config = Config::load(&path).await?;
+    let profile = config.resolve(profile).to_string();
+    // Keep the app instance of an earlier login to this profile
+    let previous = config.credentials(&profile).cloned().unwrap_or_default();
+
+    let credentials = match client
+        .reauthorize(
+            &previous,
+            AuthorizeOptions::from(
+                [
+                    AccessScope::AccountInfo,
+                    AccessScope::OperationHistory,
+                    AccessScope::PaymentP2P,
+                ]
+                .into_iter()
+                .collect::<std::collections::HashSet<_>>(),
+            ),
+            |redirect_addr| async move {
+                println!("Please open this page in your browser: {redirect_addr}");
+                println!("Copy and paste your redirect URI here");
+
+                let mut stdin = tokio_util::codec::FramedRead::new(
+                    tokio::io::stdin(),
+                    tokio_util::codec::LinesCodec::new(),
+                );
+                let uri = stdin.next().await.unwrap().unwrap();
+
+                let response = AuthorizationResponse::from_redirect_url(&uri)?;
+
+                println!("Extracted token: {}", response.code);
+
+                Ok(response)
+            },
+        )
+        .await
+    {
+        Ok(credentials) => credentials,
+        Err(e) => {
+            if let Some(yoomoney::Error::AuthorizationDenied { reason }) = e.downcast_ref() {
+                println!("You denied access to your account ({reason}), nothing was saved");
+                return Ok(());
+            }
+            return Err(e);
+        }
+    };
+
+    let permanent_token = credentials.access_token.clone();
+    if !do_not_store_on_disk {
+        println!(
+            "Saving token on disk to {} as profile {profile}",
+            path.to_string_lossy()
+        );
+        config.profiles.insert(profile, credentials);
+        config.save(path).await?;
+    }
+
+    println!("Your permanent token is {permanent_token:?}");
+
+    Ok(())
+}
+
+/// Execute a profile management command against the config file.
+pub async fn run_profile(cmd: ProfileCmd) -> anyhow::Result<()> {
+    let path = config_location();
+    let mut config = Config::load(&path).await?;
+    match cmd {
+        ProfileCmd::List => {
+            for (name, credentials) in &config.profiles {
+                let marker = if *name == config.default_profile {
+                    "*"
+                } else {
+                    " "
+                };
+                println!(
+                    "{marker} {name} {}",
+                    redact_token(&credentials.access_token)
+                );
+            }
+            return Ok(());
+        }
+        ProfileCmd::Add { name, token } => {
+            config.profiles.insert(name, StoredCredentials::new(token));
+        }
+        ProfileCmd::Remove { name } => {
+            if config.profiles.remove(&name).is_none() {
+                bail!("No profile named {name}");
+            }
+        }
+        ProfileCmd::Default { name } => {
+            if !config.profiles.contains_key(&name) {
+                bail!("No profile named {name}");
+            }
+            config.default_profile = name;
+        }
+    }
+    config.save(path).await
+}
+
+/// Amounts of a requested transfer shown before confirming it.
+///
+/// The request-payment response only reports the amount debited (`contract_amount`), so the other
+/// side is derived from the requested amount. Whatever the response lacks is estimated from the
+/// fee schedule and flagged as such.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TransferSummary {
+    /// Amount the recipient gets.
+    pub net: BigDecimal,
+    pub net_estimated: bool,
+    /// Amount debited from the sender, fee included.
+    pub total: BigDecimal,
+    pub total_estimated: bool,
+}
+
+impl TransferSummary {
+    #[must_use]
+    pub fn new(
+        requested: &RequestAmount,
+        contract_amount: Option<&BigDecimal>,
+        fees: &FeeSchedule,
+    ) -> Self {
+        match requested {
+            RequestAmount::Net(net) => Self {
+                net: net.clone(),
+                net_estimated: false,
+                total: contract_amount
+                    .cloned()
+                    .unwrap_or_else(|| fees.total_for_net(net, FeeSource::Wallet)),
+                total_estimated: contract_amount.is_none(),
+            },
+            RequestAmount::Total(total) => {
+                let total = contract_amount.unwrap_or(total).clone();
+                Self {
+                    net: fees.net_for_total(&total, FeeSource::Wallet),
+                    net_estimated: true,
+                    total,
+                    total_estimated: false,
+                }
+            }
+        }
+    }
+
+    #[must_use]
+    pub fn fee(&self) -> BigDecimal {
+        &self.total - &self.net
+    }
+}
+
+/// Ask the user a yes/no question on the terminal. Anything but "yes" is a no.
+async fn confirm(question: &str) -> anyhow::Result<bool> {
+    println!("{question} Type \"yes\" to proceed:");
+
+    let mut stdin = tokio_util::codec::FramedRead::new(
+        tokio::io::stdin(),
+        tokio_util::codec::LinesCodec::new(),
+    );
+    let answer = stdin.next().await.transpose()?.unwrap_or_default();
+
+    Ok(answer.trim() == "yes")
+}
+
+/// Command line for a logged in user, for embedding these commands into another tool.
+///
+/// Matches can be turned back into commands with [`Cli::from_arg_matches`] and then executed with [`run`].
+pub fn build_cli() -> Command {
+    Cli::<AuthorizedCmd>::command()
+}
+
+/// Command line for a user without a token yet.
+pub fn build_unauthorized_cli() -> Command {
+    Cli::<UnauthorizedCmd>::command()
+}
+
+/// Execute a command on behalf of the user `client` is authorized as.
+pub async fn run(
+    cmd: AuthorizedCmd,
+    client: Client,
+    profile: Option<&str>,
+    output: Output,
+) -> anyhow::Result<()> {
+    match cmd {
+        AuthorizedCmd::Login(data) => do_authorize(data, profile).await?,
+        AuthorizedCmd::Profile(cmd) => run_profile(cmd).await?,
+        AuthorizedCmd::Revoke => {
+            client.revoke_token().await?;
+            println!("Token successfully revoked");
+        }
+        AuthorizedCmd::Export { format } => {
+            let credentials = load_credentials(profile)
+                .await
+                .ok_or_else(|| format_err!("No stored credentials"))?;
+            eprintln!(
+                "Warning: output contains your token. Avoid putting it on the command line or into shell history"
+            );
+            print!("{}", credentials.export(format.into())?);
+        }
+        AuthorizedCmd::RequestTransfer {
+            to,
+            amount,
+            comment,
+            message,
+            label,
+            codepro,
+            protection_code,
+            hold_for_pickup,
+            expire_period,
+            yes,
+        } => {
+            let to: UserId =
+                Option::from(to).ok_or_else(|| format_err!("User ID not specified"))?;
+            let amount: RequestAmount =
+                Option::from(amount).ok_or_else(|| format_err!("Transfer amount not specified"))?;
+
+            let recipient = to.to_string();
+            let requested = amount.clone();
+            let payment_request = client.request_transfer(
+                to,
+                amount,
+                comment.unwrap_or_default(),
+                message.unwrap_or_default(),
+                label,
+                codepro.unwrap_or_default(),
+                protection_code,
+                hold_for_pickup.unwrap_or_default(),
+                expire_period.unwrap_or_default(),
+            );
+
+            let rsp = payment_request.send().await?;
+            if let Some(uri) = rsp.action_uri() {
+                println!("Action required before paying, please visit {uri}");
+            }
+            let (_, data) = rsp
+                .into_result()
+                .map_err(|e| format_err!("Payment request refused: {e}"))?;
+
+            let summary = TransferSummary::new(
+                &requested,
+                data.contract_amount.as_ref(),
+                client.fee_schedule(),
+            );
+            let estimate = |estimated| if estimated { " (estimate)" } else { "" };
+            println!("Recipient: {recipient}");
+            println!(
+                "Amount: {}{}",
+                output.amount(&summary.net),
+                estimate(summary.net_estimated)
+            );
+            println!(
+                "Fee: {}{}",
+                output.amount(&summary.fee()),
+                estimate(summary.net_estimated || summary.total_estimated)
+            );
+            println!(
+                "Total with fee: {}{}",
+                output.amount(&summary.total),
+                estimate(summary.total_estimated)
+            );
+
+            if !yes && !client.is_sandbox() && !confirm("Send this transfer?").await? {
+                println!("Transfer cancelled");
+                return Ok(());
+            }
+
+            let res = client
+                .process_payment(data.request_id, ProcessPaymentMoneySource::Wallet)
+                .await?;
+            print_payment_result(&res, &output);
+        }
+        AuthorizedCmd::ProcessPayment {
+            request_id,
+            money_source,
+        } => {
+            let res = client.process_payment(request_id, money_source).await?;
+            print_payment_result(&res, &output);
+        }
+        AuthorizedCmd::OperationHistory {
+            detailed,
+            from,
+            till,
+            last_days,
+        } => {
+            let DateRange { from, till } = match last_days {
+                Some(days) => DateRange::last_days(days),
+                None => DateRange { from, till },
+            };
+
+            let mut history =
+                client.operation_history(Default::default(), None, from, till, 0, detailed, false);
+
+            while let Some(v) = history.next().await.transpose()? {
+                println!(
+                    "{} {:>12} {} [{}]",
+                    v.datetime,
+                    output.amount(&v.signed_amount()),
+                    v.title,
+                    v.operation_id
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn print_payment_result(res: &ProcessPaymentResponse, output: &Output) {
+    match res {
+        ProcessPaymentResponse::Success(data) => println!(
+            "Payment {} done, {} credited, balance is {}",
+            data.payment_id,
+            output.amount(&data.credit_amount),
+            output.amount(&data.balance)
+        ),
+        other => println!("Payment result is {other:?}"),
+    }
+}
@@ -0,0 +1,79 @@
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, path::Path};
+use yoomoney::{write_private_file, StoredCredentials};
+
+pub const DEFAULT_PROFILE: &str = "default";
+
+fn default_profile_name() -> String {
+    DEFAULT_PROFILE.to_string()
+}
+
+/// CLI configuration file: credentials for each named wallet.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Config {
+    /// Profile used when `--profile` is not given
+    #[serde(default = "default_profile_name")]
+    pub default_profile: String,
+    #[serde(default)]
+    pub profiles: BTreeMap<String, StoredCredentials>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            default_profile: default_profile_name(),
+            profiles: BTreeMap::new(),
+        }
+    }
+}
+
+impl Config {
+    /// Parse configuration, accepting the old flat single-token layout as the `default` profile.
+    ///
+    /// Returns whether the old layout was found, so that the caller can save it migrated.
+    pub fn parse(s: &str) -> anyhow::Result<(Self, bool)> {
+        let value = toml::from_str::<toml::Value>(s)?;
+        if value.get("access_token").is_some() || value.get("token").is_some() {
+            let credentials = value.try_into::<StoredCredentials>()?;
+            let mut config = Self::default();
+            config.profiles.insert(default_profile_name(), credentials);
+            return Ok((config, true));
+        }
+
+        Ok((value.try_into()?, false))
+    }
+
+    /// Load configuration from `path`, migrating the old layout in place. A missing file is an empty configuration.
+    pub async fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let s = match tokio::fs::read_to_string(path).await {
+            Ok(s) => s,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e.into()),
+        };
+        let (config, migrated) = Self::parse(&s)?;
+        if migrated {
+            config.save(path).await?;
+        }
+
+        Ok(config)
+    }
+
+    /// Write configuration as TOML, creating parent directories as needed.
+    ///
+    /// It holds the tokens of every profile, so the file is only readable by its owner.
+    pub async fn save(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        write_private_file(path.as_ref(), &toml::to_string(self)?).await
+    }
+
+    /// Name of the profile to use: the requested one, or else the configured default.
+    #[must_use]
+    pub fn resolve<'a>(&'a self, requested: Option<&'a str>) -> &'a str {
+        requested.unwrap_or(&self.default_profile)
+    }
+
+    #[must_use]
+    pub fn credentials(&self, profile: &str) -> Option<&StoredCredentials> {
+        self.profiles.get(profile)
+    }
+}
@@ -0,0 +1,217 @@
+use clap::{FromArgMatches, Parser};
+use yoomoney::{FeeSchedule, RequestAmount};
+use yoomoney_cli::*;
+
+fn parse(args: &[&str]) -> Result<Cli<AuthorizedCmd>, clap::Error> {
+    Cli::try_parse_from(std::iter::once("yoomoney-cli").chain(args.iter().copied()))
+}
+
+#[test]
+fn commands_are_consistent() {
+    build_cli().debug_assert();
+    build_unauthorized_cli().debug_assert();
+}
+
+#[test]
+fn request_transfer_args() {
+    let cli = parse(&[
+        "request-transfer",
+        "--to-account",
+        "4100123456789",
+        "--amount-net",
+        "10.50",
+        "--comment",
+        "rent",
+    ])
+    .unwrap();
+    assert!(matches!(
+        cli.cmd,
+        AuthorizedCmd::RequestTransfer {
+            to: To {
+                to_account: Some(4_100_123_456_789),
+                ..
+            },
+            amount: Amount {
+                amount_net: Some(_),
+                amount_total: None,
+            },
+            ..
+        }
+    ));
+
+    assert!(parse(&[
+        "request-transfer",
+        "--to-account",
+        "4100123456789",
+        "--to-email",
+        "user@example.com",
+    ])
+    .is_err());
+    assert!(parse(&[
+        "request-transfer",
+        "--amount-net",
+        "1",
+        "--amount-total",
+        "1"
+    ])
+    .is_err());
+}
+
+#[test]
+fn operation_history_args() {
+    let cli = parse(&[
+        "--log-format",
+        "json",
+        "operation-history",
+        "--last-days",
+        "7",
+    ])
+    .unwrap();
+    assert!(matches!(cli.log_format, LogFormat::Json));
+    assert!(matches!(
+        cli.cmd,
+        AuthorizedCmd::OperationHistory {
+            last_days: Some(7),
+            ..
+        }
+    ));
+
+    assert!(parse(&[
+        "operation-history",
+        "--last-days",
+        "7",
+        "--from",
+        "2020-01-01T00:00:00Z"
+    ])
+    .is_err());
+}
+
+#[test]
+fn embedded_as_subcommand() {
+    let matches = clap::Command::new("tool")
+        .subcommand(build_cli().name("yoomoney"))
+        .get_matches_from(["tool", "yoomoney", "revoke"]);
+    let (_, matches) = matches.subcommand().unwrap();
+    let cli = Cli::<AuthorizedCmd>::from_arg_matches(matches).unwrap();
+    assert!(matches!(cli.cmd, AuthorizedCmd::Revoke));
+}
+
+#[test]
+fn raw_amounts_flag() {
+    let amount = "10.5".parse().unwrap();
+
+    let cli = parse(&["operation-history"]).unwrap();
+    assert_eq!(cli.output.amount(&amount), "10.50");
+
+    let cli = parse(&["operation-history", "--raw-amounts"]).unwrap();
+    assert_eq!(cli.output.amount(&amount), "10.5");
+}
+
+#[test]
+fn process_payment_args() {
+    let cli = parse(&[
+        "process-payment",
+        "--request-id",
+        "request",
+        "--money-source",
+        "wallet",
+    ])
+    .unwrap();
+    assert!(matches!(
+        cli.cmd,
+        AuthorizedCmd::ProcessPayment {
+            money_source: yoomoney::ProcessPaymentMoneySource::Wallet,
+            ..
+        }
+    ));
+}
+
+#[test]
+fn flat_config_is_migrated() {
+    let (config, migrated) = Config::parse("access_token = \"abc\"\n").unwrap();
+    assert!(migrated);
+    assert_eq!(config.default_profile, DEFAULT_PROFILE);
+    assert_eq!(
+        config.credentials(DEFAULT_PROFILE).unwrap().access_token,
+        "abc"
+    );
+
+    let saved = toml::to_string(&config).unwrap();
+    let (reloaded, migrated) = Config::parse(&saved).unwrap();
+    assert!(!migrated);
+    assert_eq!(
+        reloaded.credentials(DEFAULT_PROFILE).unwrap().access_token,
+        "abc"
+    );
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn migrated_config_is_private() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = std::env::temp_dir().join(format!("yoomoney-cli-config-{}", std::process::id()));
+    let path = dir.join("config.toml");
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(&path, "access_token = \"abc\"\n").unwrap();
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+
+    // Loading the old layout saves it migrated
+    let config = Config::load(&path).await.unwrap();
+    assert!(config.credentials(DEFAULT_PROFILE).is_some());
+    let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn profile_selection() {
+    let (config, _) = Config::parse(
+        r#"
+default_profile = "work"
+
+[profiles.work]
+access_token = "w"
+
+[profiles.home]
+access_token = "h"
+"#,
+    )
+    .unwrap();
+    assert_eq!(config.resolve(None), "work");
+    assert_eq!(config.resolve(Some("home")), "home");
+
+    let cli = parse(&["--profile", "home", "profile", "list"]).unwrap();
+    assert_eq!(cli.profile.as_deref(), Some("home"));
+    assert!(matches!(cli.cmd, AuthorizedCmd::Profile(ProfileCmd::List)));
+}
+
+#[test]
+fn transfer_summary() {
+    let fees = FeeSchedule::default();
+    let dec = |s: &str| s.parse::<bigdecimal::BigDecimal>().unwrap();
+
+    // Net requested: total and fee come from the response
+    let summary =
+        TransferSummary::new(&RequestAmount::Net(dec("100")), Some(&dec("100.50")), &fees);
+    assert_eq!(summary.net, dec("100"));
+    assert_eq!(summary.fee(), dec("0.50"));
+    assert!(!summary.net_estimated && !summary.total_estimated);
+
+    // Total requested: what the recipient gets can only be estimated
+    let summary = TransferSummary::new(
+        &RequestAmount::Total(dec("100.50")),
+        Some(&dec("100.50")),
+        &fees,
+    );
+    assert_eq!(summary.total, dec("100.50"));
+    assert_eq!(summary.net, dec("100"));
+    assert_eq!(summary.fee(), dec("0.50"));
+    assert!(summary.net_estimated && !summary.total_estimated);
+
+    // No contract amount: total estimated from the fee schedule
+    let summary = TransferSummary::new(&RequestAmount::Net(dec("100")), None, &fees);
+    assert_eq!(summary.total, dec("100.50"));
+    assert!(summary.total_estimated);
+}
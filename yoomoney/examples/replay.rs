@@ -0,0 +1,150 @@
+//! Walk through a typical session against recorded responses, without network access.
+//!
+//! Fixtures live in `examples/fixtures`, one JSON file per endpoint, named after the endpoint
+//! with `/` replaced by `_`. They contain no real account data. Run with:
+//!
+//! ```sh
+//! cargo run -p yoomoney --example replay [fixtures-dir]
+//! ```
+
+use bigdecimal::BigDecimal;
+use maplit::hashset;
+use std::{
+    collections::HashMap,
+    future::Future,
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::Arc,
+};
+use tokio_stream::StreamExt;
+use yoomoney::*;
+
+/// Transport answering every call with the recorded response for its endpoint.
+#[derive(Debug)]
+struct ReplayTransport {
+    fixtures: HashMap<String, serde_json::Value>,
+}
+
+impl ReplayTransport {
+    fn load(dir: &Path) -> anyhow::Result<Self> {
+        let mut fixtures = HashMap::new();
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "json") {
+                let endpoint = path
+                    .file_stem()
+                    .expect("file has an extension, so it has a stem; qed")
+                    .to_string_lossy()
+                    .replace('_', "/");
+                let fixture = serde_json::from_str(&std::fs::read_to_string(&path)?)?;
+                fixtures.insert(endpoint, fixture);
+            }
+        }
+
+        Ok(Self { fixtures })
+    }
+
+    fn fixture(&self, endpoint: &str) -> anyhow::Result<&serde_json::Value> {
+        self.fixtures
+            .get(endpoint)
+            .ok_or_else(|| anyhow::format_err!("no fixture recorded for {endpoint}"))
+    }
+}
+
+impl Transport for ReplayTransport {
+    fn call(
+        &self,
+        endpoint: &'static str,
+        _params: &HashMap<&str, String>,
+    ) -> Pin<Box<dyn Future<Output = anyhow::Result<String>> + Send + 'static>> {
+        let rsp = self.fixture(endpoint).map(ToString::to_string);
+        Box::pin(async move { rsp })
+    }
+
+    fn get_redirect(
+        &self,
+        endpoint: &'static str,
+        _params: &HashMap<&str, String>,
+    ) -> Pin<Box<dyn Future<Output = anyhow::Result<String>> + Send + 'static>> {
+        let rsp = self.fixture(endpoint).and_then(|v| {
+            v.as_str().map(ToString::to_string).ok_or_else(|| {
+                anyhow::format_err!("redirect fixture for {endpoint} must be a string")
+            })
+        });
+        Box::pin(async move { rsp })
+    }
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let dir = std::env::args().nth(1).map_or_else(
+        || PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("examples/fixtures"),
+        PathBuf::from,
+    );
+    let transport = Arc::new(ReplayTransport::load(&dir)?);
+
+    let token = UnauthorizedClient::from_transport(
+        transport.clone(),
+        "replay-client".into(),
+        "https://example.com/redirect".into(),
+    )
+    .authorize_with_options(
+        AuthorizeOptions {
+            // Fixed, so that the replayed redirect carries it back
+            state: Some("replay-state".into()),
+            ..hashset! { AccessScope::AccountInfo, AccessScope::OperationHistory, AccessScope::PaymentP2P }
+                .into()
+        },
+        |redirect_addr| async move {
+            println!("Would open {redirect_addr} in browser");
+            Ok(AuthorizationResponse {
+                code: "replay-code".into(),
+                state: Some("replay-state".into()),
+            })
+        },
+    )
+    .await?;
+    println!("Authorized with token {token}");
+
+    let client = Client::from_transport(transport);
+
+    let account_info = client.account_info().await?;
+    println!(
+        "Account {} has {} available",
+        account_info.account, account_info.balance
+    );
+
+    let (_, request) = client
+        .request_transfer(
+            UserId::account(4_100_000_000_000_001)?,
+            RequestAmount::Net(BigDecimal::from(100)),
+            "Replayed transfer".into(),
+            String::new(),
+            None,
+            false,
+            None,
+            false,
+            0,
+        )
+        .send()
+        .await?
+        .into_result()
+        .map_err(|e| anyhow::format_err!("transfer refused: {e}"))?;
+    let payment = client
+        .process_payment(request.request_id, ProcessPaymentMoneySource::Wallet)
+        .await?;
+    println!("Payment result: {payment:?}");
+
+    let mut history =
+        client.operation_history(Default::default(), None, None, None, 0, false, false);
+    while let Some(operation) = history.next().await.transpose()? {
+        println!(
+            "{} {} {}",
+            operation.datetime,
+            operation.signed_amount(),
+            operation.title
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,188 @@
+use crate::{Error, Transport};
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::{fmt, future::Future, path::Path, pin::Pin, sync::Arc};
+
+/// Text formats for handing credentials over to other tools, see [`StoredCredentials::export`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CredentialsFormat {
+    /// Shell-compatible `KEY='value'` lines, starting with `TOKEN`.
+    Env,
+    Toml,
+    Json,
+}
+
+/// Token set persisted between runs.
+///
+/// YooMoney currently issues permanent access tokens only, so `refresh_token` and `expires_at`
+/// stay empty for tokens obtained through [`UnauthorizedClient::authorize`](crate::UnauthorizedClient::authorize).
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct StoredCredentials {
+    #[serde(alias = "token")]
+    pub access_token: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub refresh_token: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>,
+    /// `instance_name` the token was issued for, reused by [`UnauthorizedClient::reauthorize`](crate::UnauthorizedClient::reauthorize).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub instance_name: Option<String>,
+}
+
+impl StoredCredentials {
+    #[must_use]
+    pub fn new(access_token: String) -> Self {
+        Self {
+            access_token,
+            ..Default::default()
+        }
+    }
+
+    #[must_use]
+    pub fn is_expired(&self) -> bool {
+        self.expires_at.is_some_and(|t| t <= Utc::now())
+    }
+
+    /// Render credentials in `format`. The output contains the secret token itself.
+    #[allow(clippy::missing_errors_doc)]
+    pub fn export(&self, format: CredentialsFormat) -> anyhow::Result<String> {
+        Ok(match format {
+            CredentialsFormat::Env => {
+                let quote = |v: &str| format!("'{}'", v.replace('\'', r"'\''"));
+                let mut out = format!("TOKEN={}\n", quote(&self.access_token));
+                for (key, value) in [
+                    ("REFRESH_TOKEN", self.refresh_token.clone()),
+                    ("TOKEN_EXPIRES_AT", self.expires_at.map(|t| t.to_rfc3339())),
+                    ("TOKEN_SCOPE", self.scope.clone()),
+                    ("TOKEN_INSTANCE_NAME", self.instance_name.clone()),
+                ] {
+                    if let Some(value) = value {
+                        out.push_str(&format!("{key}={}\n", quote(&value)));
+                    }
+                }
+                out
+            }
+            CredentialsFormat::Toml => toml::to_string(self)?,
+            CredentialsFormat::Json => serde_json::to_string_pretty(self)? + "\n",
+        })
+    }
+
+    /// Write credentials as TOML, creating parent directories as needed.
+    ///
+    /// The file is only readable by its owner, see [`write_private_file`].
+    pub async fn save(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        write_private_file(path.as_ref(), &toml::to_string(self)?).await
+    }
+
+    pub async fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        Ok(toml::from_str(&tokio::fs::read_to_string(path).await?)?)
+    }
+
+    /// Load credentials, and if they are expired and have a refresh token, exchange it using `refresh`
+    /// and save the result back to `path`.
+    pub async fn load_refreshing<F, Fut>(path: impl AsRef<Path>, refresh: F) -> anyhow::Result<Self>
+    where
+        F: FnOnce(String) -> Fut,
+        Fut: Future<Output = anyhow::Result<Self>>,
+    {
+        let path = path.as_ref();
+        let credentials = Self::load(path).await?;
+        match credentials.refresh_token.clone() {
+            Some(refresh_token) if credentials.is_expired() => {
+                let refreshed = refresh(refresh_token).await?;
+                refreshed.save(path).await?;
+                Ok(refreshed)
+            }
+            _ => Ok(credentials),
+        }
+    }
+}
+
+/// Write a file holding secrets, creating parent directories as needed.
+///
+/// On Unix the contents go to a temporary file created with mode `0600` that then replaces `path`,
+/// so the secrets are never readable by others, not even while being written.
+pub async fn write_private_file(path: &Path, contents: &str) -> anyhow::Result<()> {
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+
+    #[cfg(unix)]
+    {
+        use tokio::io::AsyncWriteExt;
+
+        let mut tmp_name = path.file_name().unwrap_or_default().to_os_string();
+        tmp_name.push(".tmp");
+        let tmp = path.with_file_name(tmp_name);
+        // A leftover from an interrupted write would keep its old permissions
+        match tokio::fs::remove_file(&tmp).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+        let mut file = tokio::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&tmp)
+            .await?;
+        file.write_all(contents.as_bytes()).await?;
+        file.sync_all().await?;
+        drop(file);
+        tokio::fs::rename(&tmp, path).await?;
+    }
+    #[cfg(not(unix))]
+    tokio::fs::write(path, contents).await?;
+
+    Ok(())
+}
+
+type RefreshFn = dyn Fn(String) -> Pin<Box<dyn Future<Output = anyhow::Result<StoredCredentials>> + Send>>
+    + Send
+    + Sync;
+
+/// Refresh token and the function exchanging it, see [`Client::with_token_refresh`](crate::Client::with_token_refresh).
+#[derive(Clone)]
+pub(crate) struct TokenRefresh {
+    refresh_token: Arc<tokio::sync::Mutex<Option<String>>>,
+    refresh: Arc<RefreshFn>,
+}
+
+impl fmt::Debug for TokenRefresh {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TokenRefresh").finish_non_exhaustive()
+    }
+}
+
+impl TokenRefresh {
+    pub(crate) fn new<F, Fut>(refresh_token: String, refresh: F) -> Self
+    where
+        F: Fn(String) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = anyhow::Result<StoredCredentials>> + Send + 'static,
+    {
+        Self {
+            refresh_token: Arc::new(tokio::sync::Mutex::new(Some(refresh_token))),
+            refresh: Arc::new(move |token| Box::pin(refresh(token))),
+        }
+    }
+
+    /// Exchange the refresh token and switch `transport` over to the new access token.
+    pub(crate) async fn run(&self, transport: &dyn Transport) -> anyhow::Result<()> {
+        // Held across the exchange, so that concurrent refreshes don't spend the same refresh token
+        let mut refresh_token = self.refresh_token.lock().await;
+        let Some(token) = refresh_token.clone() else {
+            return Err(Error::NotAuthorized {
+                endpoint: "token refresh",
+            }
+            .into());
+        };
+        let refreshed = (self.refresh)(token).await?;
+        transport.set_bearer(refreshed.access_token)?;
+        if refreshed.refresh_token.is_some() {
+            *refresh_token = refreshed.refresh_token;
+        }
+
+        Ok(())
+    }
+}
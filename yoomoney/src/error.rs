@@ -0,0 +1,102 @@
+use thiserror::Error;
+
+/// Maximum number of characters of the response body kept in errors.
+pub const BODY_SNIPPET_LEN: usize = 512;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("failed to initialize HTTP client: {0}")]
+    ClientInit(#[source] reqwest::Error),
+    #[error("failed to initialize HTTP client for following redirects: {0}")]
+    RedirectClientBuild(#[source] reqwest::Error),
+    #[error("expected a redirect, got response with status {status}")]
+    RedirectMissing { status: u16 },
+    #[error("failed to deserialize response from {endpoint}: {source}; body: {body}")]
+    Deserialize {
+        endpoint: &'static str,
+        #[source]
+        source: serde_json::Error,
+        /// Truncated response body with long digit sequences (account, card and phone numbers) masked.
+        body: String,
+    },
+    /// Response carried fields or values unknown to this library, see [`DeserializeMode::Strict`](crate::DeserializeMode::Strict).
+    #[error("unexpected data in response from {endpoint}: {}", details.join(", "))]
+    UnexpectedResponse {
+        endpoint: &'static str,
+        details: Vec<String>,
+    },
+    #[error("operation {operation_id} lacks {field} needed for its typed view")]
+    IncompleteOperation {
+        operation_id: String,
+        field: &'static str,
+    },
+    #[error("payment request {request_id} expired at {valid_until}")]
+    RequestExpired {
+        request_id: String,
+        valid_until: chrono::DateTime<chrono::Utc>,
+    },
+    #[error("parameter {key} is reserved and can't be passed explicitly")]
+    ReservedParam { key: String },
+    #[error("{field} exceeds {max} characters")]
+    FieldTooLong { field: &'static str, max: usize },
+    #[error("port {port} is already in use")]
+    PortInUse { port: u16 },
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("operation cancelled")]
+    Cancelled,
+    #[error("authorization denied: {reason}")]
+    AuthorizationDenied { reason: String },
+    #[error("OAuth state mismatch: sent {expected}, got {actual}")]
+    StateMismatch { expected: String, actual: String },
+    #[error("OAuth state missing from redirect, expected {expected}")]
+    StateMissing { expected: String },
+    #[error("unknown access scope {scope:?}")]
+    UnknownScope { scope: String },
+    #[error("missing environment variables: {}", vars.join(", "))]
+    MissingEnv { vars: Vec<&'static str> },
+    #[error("environment variable {var} has invalid value {value:?}")]
+    InvalidEnv { var: &'static str, value: String },
+    #[error("no protection code attempts left for transfer {operation_id}")]
+    AttemptsExhausted { operation_id: String },
+    #[error("{endpoint} requires an access token, but client has none")]
+    NotAuthorized { endpoint: &'static str },
+    /// HTTP 401: the token is invalid, expired or was revoked.
+    #[error("{endpoint} rejected the access token as invalid or revoked")]
+    InvalidToken { endpoint: &'static str },
+    #[error("response body exceeds {limit} bytes")]
+    ResponseTooLarge { limit: usize },
+    /// An HTML page was served instead of an API response, typically during maintenance.
+    #[error("service unavailable, got an HTML page with status {status}")]
+    Maintenance { status: u16 },
+}
+
+/// Prepare response body for inclusion in error reports: mask long digit runs and truncate.
+pub fn redact_body(body: &str) -> String {
+    let mut out = String::with_capacity(body.len().min(BODY_SNIPPET_LEN));
+    let mut digits = String::new();
+    let flush = |out: &mut String, digits: &mut String| {
+        if digits.len() >= 10 {
+            out.push_str("***");
+        } else {
+            out.push_str(digits);
+        }
+        digits.clear();
+    };
+    for c in body.chars() {
+        if c.is_ascii_digit() {
+            digits.push(c);
+        } else {
+            flush(&mut out, &mut digits);
+            out.push(c);
+        }
+    }
+    flush(&mut out, &mut digits);
+
+    if out.chars().count() > BODY_SNIPPET_LEN {
+        out = out.chars().take(BODY_SNIPPET_LEN).collect();
+        out.push('…');
+    }
+
+    out
+}
@@ -1,22 +1,47 @@
+mod credentials;
+mod error;
+mod loopback;
 mod models;
+mod notifications;
+#[cfg(feature = "otel")]
+mod otel;
+mod showcase;
+mod signing;
+mod stream;
 mod transport;
 
+pub use credentials::*;
+pub use error::*;
+pub use loopback::*;
 pub use models::*;
+pub use notifications::*;
+#[cfg(feature = "otel")]
+pub use otel::with_trace_context;
+pub use showcase::*;
+pub use signing::*;
+pub use stream::*;
 pub use transport::*;
 
-use async_stream::try_stream;
+use anyhow::format_err;
+use async_stream::stream;
 use async_trait::async_trait;
 use bigdecimal::BigDecimal;
 use chrono::prelude::*;
 use itertools::*;
-use maplit::hashmap;
+use maplit::{hashmap, hashset};
 use phonenumber::PhoneNumber;
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
     fmt::Display,
     future::Future,
     pin::Pin,
     sync::Arc,
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncWrite, AsyncWriteExt},
+    sync::Semaphore,
+    task::JoinSet,
 };
 use tokio_stream::*;
 use uuid::Uuid;
@@ -24,6 +49,12 @@ use uuid::Uuid;
 #[async_trait]
 pub trait API {
     async fn account_info(&self) -> anyhow::Result<AccountInfo>;
+    /// Stream operation history, newest first.
+    ///
+    /// The API only returns operations in descending order. With `ascending` set, all pages are fetched
+    /// and buffered in memory before being yielded oldest first, so narrow the range with `from`/`till`
+    /// when pulling long histories this way.
+    #[allow(clippy::too_many_arguments)]
     fn operation_history(
         &self,
         operation_types: HashSet<ReqOperationType>,
@@ -32,6 +63,7 @@ pub trait API {
         till: Option<DateTime<Utc>>,
         start_record: u64,
         details: bool,
+        ascending: bool,
     ) -> Pin<Box<dyn Stream<Item = anyhow::Result<Operation>> + Send>>;
     async fn operation_details(&self, operation_id: String) -> anyhow::Result<OperationDetails>;
     fn request_shop_payment(
@@ -48,6 +80,7 @@ pub trait API {
         message: String,
         label: Option<String>,
         codepro: bool,
+        protection_code: Option<ProtectionCode>,
         hold_for_pickup: bool,
         expire_period: u32,
     ) -> PaymentRequest;
@@ -71,66 +104,1229 @@ pub trait PaymentRequestTrait {
 pub struct PaymentRequest {
     caller: CallerWrapper,
     params: HashMap<String, String>,
+    /// Reported on send, as building the request is infallible.
+    invalid: Option<Error>,
 }
 
 #[async_trait]
 impl PaymentRequestTrait for PaymentRequest {
     async fn send(self) -> anyhow::Result<RequestPaymentResponse> {
+        if let Some(e) = self.invalid {
+            return Err(e.into());
+        }
+
         let params = self
             .params
             .iter()
             .map(|(k, v)| (k.as_str(), v.clone()))
             .collect();
+        // Refusals carry details besides `error`, which `Rsp` would discard
+        self.caller.call_plain("api/request-payment", &params).await
+    }
+}
+
+pub struct TestPaymentRequest {
+    inner: PaymentRequest,
+}
+
+impl From<PaymentRequest> for TestPaymentRequest {
+    fn from(inner: PaymentRequest) -> Self {
+        Self { inner }
+    }
+}
+
+#[async_trait]
+impl PaymentRequestTrait for TestPaymentRequest {
+    async fn send(mut self) -> anyhow::Result<RequestPaymentResponse> {
+        self.inner
+            .params
+            .insert("test_payment".to_string(), true.to_string());
+
+        Ok(self.inner.send().await?)
+    }
+}
+
+/// Parameters of `request-payment` that [`API::request_shop_payment`] does not accept from callers.
+pub const RESERVED_SHOP_PARAMS: &[&str] = &["pattern_id", "test_payment"];
+
+/// Kept low, as each operation costs a separate details request.
+pub const DEFAULT_DETAILS_CONCURRENCY: usize = 2;
+
+pub const DEFAULT_ADDR: &str = "https://money.yandex.ru";
+/// Testing environment, where no real money is moved.
+pub const SANDBOX_ADDR: &str = "https://demomoney.yandex.ru";
+
+/// Variable holding the access token for [`Client::from_env`].
+pub const ENV_TOKEN: &str = "YOOMONEY_TOKEN";
+
+/// Lines written between flushes by [`Client::operation_history_to_writer`].
+pub const NDJSON_FLUSH_EVERY: u64 = 100;
+
+#[derive(Clone, Debug)]
+pub struct ClientBuilder {
+    addr: String,
+    token: Option<String>,
+    app_name: Option<String>,
+    app_version: Option<String>,
+    fee_schedule: FeeSchedule,
+    sandbox: bool,
+    truncate_transfer_text: bool,
+    max_concurrent_requests: Option<usize>,
+    details_concurrency: usize,
+    deserialize_mode: DeserializeMode,
+    options: HttpOptions,
+}
+
+impl Default for ClientBuilder {
+    fn default() -> Self {
+        Self {
+            addr: DEFAULT_ADDR.into(),
+            token: None,
+            app_name: None,
+            app_version: None,
+            fee_schedule: FeeSchedule::default(),
+            sandbox: false,
+            truncate_transfer_text: false,
+            max_concurrent_requests: None,
+            details_concurrency: DEFAULT_DETAILS_CONCURRENCY,
+            deserialize_mode: DeserializeMode::default(),
+            options: HttpOptions::default(),
+        }
+    }
+}
+
+impl ClientBuilder {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[must_use]
+    pub fn addr(mut self, addr: impl Display) -> Self {
+        self.addr = addr.to_string();
+        self
+    }
+
+    /// Point the client at [`SANDBOX_ADDR`] and mark it as such, see [`Client::is_sandbox`].
+    #[must_use]
+    pub fn sandbox(mut self) -> Self {
+        self.addr = SANDBOX_ADDR.into();
+        self.sandbox = true;
+        self
+    }
+
+    #[must_use]
+    pub fn token(mut self, token: impl Display) -> Self {
+        self.token = Some(token.to_string());
+        self
+    }
+
+    /// Override fee rates used for offline estimates, see [`Client::fee_schedule`].
+    #[must_use]
+    pub fn fee_schedule(mut self, fee_schedule: FeeSchedule) -> Self {
+        self.fee_schedule = fee_schedule;
+        self
+    }
+
+    /// Truncate over-long transfer comment and message instead of rejecting them with [`Error::FieldTooLong`].
+    #[must_use]
+    pub fn truncate_transfer_text(mut self, truncate: bool) -> Self {
+        self.truncate_transfer_text = truncate;
+        self
+    }
+
+    /// Limit requests in flight at once across the client, including streams and
+    /// [`with_bearer`](Client::with_bearer) copies. Unlimited by default.
+    #[must_use]
+    pub fn max_concurrent_requests(mut self, limit: usize) -> Self {
+        self.max_concurrent_requests = Some(limit);
+        self
+    }
+
+    /// Operation details fetched at once by [`Client::with_details_fetch`].
+    /// Defaults to [`DEFAULT_DETAILS_CONCURRENCY`].
+    #[must_use]
+    pub fn details_concurrency(mut self, concurrency: usize) -> Self {
+        self.details_concurrency = concurrency;
+        self
+    }
+
+    /// Whether to tolerate unknown fields and enum values in responses. Lenient by default.
+    #[must_use]
+    pub fn deserialize_mode(mut self, mode: DeserializeMode) -> Self {
+        self.deserialize_mode = mode;
+        self
+    }
+
+    /// Name of the application put in the `User-Agent` header, so that YooMoney can identify the merchant.
+    #[must_use]
+    pub fn app_name(mut self, app_name: &str) -> Self {
+        self.app_name = Some(app_name.to_string());
+        self
+    }
+
+    #[must_use]
+    pub fn app_version(mut self, app_version: &str) -> Self {
+        self.app_version = Some(app_version.to_string());
+        self
+    }
+
+    /// Language of server error messages and operation titles, e.g. `en`. Defaults to [`DEFAULT_LANGUAGE`].
+    #[must_use]
+    pub fn language(mut self, language: &str) -> Self {
+        self.options.language = language.to_string();
+        self
+    }
+
+    /// Sign every request, e.g. with [`HmacSha256Signer`] for endpoints that require it.
+    #[must_use]
+    pub fn request_signer(mut self, signer: impl RequestSigner + 'static) -> Self {
+        self.options.signer = Arc::new(signer);
+        self
+    }
+
+    /// Send params as form or JSON body. Form by default, as expected by most of the API.
+    /// OAuth authorization requests are always sent as form.
+    #[must_use]
+    pub fn body_encoding(mut self, encoding: BodyEncoding) -> Self {
+        self.options.body_encoding = encoding;
+        self
+    }
+
+    /// Body encoding of a single endpoint, e.g. `api/account-info`, overriding [`body_encoding`](Self::body_encoding).
+    #[must_use]
+    pub fn endpoint_body_encoding(mut self, endpoint: &str, encoding: BodyEncoding) -> Self {
+        self.options
+            .endpoint_body_encodings
+            .insert(endpoint.to_string(), encoding);
+        self
+    }
+
+    #[must_use]
+    pub fn retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.options.retry_policy = retry_policy;
+        self
+    }
+
+    /// Reject responses with bodies larger than this. Defaults to [`DEFAULT_MAX_RESPONSE_BYTES`].
+    #[must_use]
+    pub fn max_response_bytes(mut self, limit: usize) -> Self {
+        self.options.max_response_bytes = limit;
+        self
+    }
+
+    /// Accept gzip, brotli and deflate encoded responses. Enabled by default.
+    #[must_use]
+    pub fn compression(mut self, enabled: bool) -> Self {
+        self.options.compression = enabled;
+        self
+    }
+
+    #[must_use]
+    pub fn request_timeout(mut self, timeout: Duration) -> Self {
+        self.options.request_timeout = Some(timeout);
+        self
+    }
+
+    /// Fail fast on unreachable hosts, independently of [`request_timeout`](Self::request_timeout).
+    #[must_use]
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.options.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// Disable TLS certificate verification.
+    ///
+    /// **DANGEROUS**: any certificate, including self-signed and expired ones, will be trusted,
+    /// exposing the token and payment data to anyone on the network path.
+    /// Only intended for testing against a local mock server. Never enable in production.
+    #[cfg(feature = "dangerous")]
+    #[must_use]
+    pub fn danger_accept_invalid_certs(mut self, accept_invalid_certs: bool) -> Self {
+        self.options.danger_accept_invalid_certs = accept_invalid_certs;
+        self
+    }
+
+    fn build_caller(mut self) -> Result<CallerWrapper, Error> {
+        self.options.user_agent = user_agent(self.app_name.as_deref(), self.app_version.as_deref());
+        let http_client = self
+            .options
+            .apply(reqwest::Client::builder())
+            .build()
+            .map_err(Error::ClientInit)?;
+        Ok(CallerWrapper {
+            transport: Arc::new(RemoteCaller {
+                http_client,
+                addr: self.addr.into(),
+                bearer: self.token.into(),
+                options: self.options,
+            }),
+            limiter: self
+                .max_concurrent_requests
+                .map(|n| Arc::new(Semaphore::new(n.max(1)))),
+            mode: self.deserialize_mode,
+        })
+    }
+
+    /// Build the client, failing if the HTTP client (e.g. TLS backend) could not be initialized.
+    #[allow(clippy::missing_errors_doc)]
+    pub fn try_build(self) -> Result<Client, Error> {
+        let config = ClientConfig {
+            base_url: Some(self.addr.clone()),
+            sandbox: self.sandbox,
+            retry_policy: self.options.retry_policy,
+            request_timeout: self.options.request_timeout,
+            connect_timeout: self.options.connect_timeout,
+            max_response_bytes: self.options.max_response_bytes,
+            max_concurrent_requests: self.max_concurrent_requests,
+            details_concurrency: self.details_concurrency,
+            truncate_transfer_text: self.truncate_transfer_text,
+            deserialize_mode: self.deserialize_mode,
+            language: self.options.language.clone(),
+        };
+        Ok(Client {
+            fee_schedule: self.fee_schedule.clone(),
+            config,
+            caller: self.build_caller()?,
+            token_refresh: None,
+        })
+    }
+
+    #[allow(clippy::missing_errors_doc)]
+    pub fn try_build_unauthorized(
+        self,
+        client_id: String,
+        redirect_uri: String,
+    ) -> Result<UnauthorizedClient, Error> {
+        Ok(UnauthorizedClient {
+            caller: self.build_caller()?,
+            client_id,
+            redirect_uri,
+        })
+    }
+
+    /// # Panics
+    /// If the HTTP client could not be initialized, see [`try_build`](Self::try_build).
+    #[must_use]
+    pub fn build(self) -> Client {
+        self.try_build().unwrap()
+    }
+
+    /// # Panics
+    /// If the HTTP client could not be initialized, see [`try_build_unauthorized`](Self::try_build_unauthorized).
+    #[must_use]
+    pub fn build_unauthorized(self, client_id: String, redirect_uri: String) -> UnauthorizedClient {
+        self.try_build_unauthorized(client_id, redirect_uri)
+            .unwrap()
+    }
+}
+
+/// Effective settings of a [`Client`], see [`Client::config`].
+#[derive(Clone, Debug)]
+pub struct ClientConfig {
+    /// Base URL the client was built with. It may since have been changed with [`Client::set_base_url`].
+    pub base_url: Option<String>,
+    pub sandbox: bool,
+    pub retry_policy: RetryPolicy,
+    pub request_timeout: Option<Duration>,
+    pub connect_timeout: Option<Duration>,
+    pub max_response_bytes: usize,
+    pub max_concurrent_requests: Option<usize>,
+    pub details_concurrency: usize,
+    pub truncate_transfer_text: bool,
+    pub deserialize_mode: DeserializeMode,
+    pub language: String,
+}
+
+impl ClientConfig {
+    /// Configuration of a client built around a custom transport, whose own settings are unknown.
+    fn for_transport(transport: &dyn Transport) -> Self {
+        let options = HttpOptions::default();
+        Self {
+            base_url: transport.base_url(),
+            sandbox: false,
+            retry_policy: options.retry_policy,
+            request_timeout: options.request_timeout,
+            connect_timeout: options.connect_timeout,
+            max_response_bytes: options.max_response_bytes,
+            max_concurrent_requests: None,
+            details_concurrency: DEFAULT_DETAILS_CONCURRENCY,
+            truncate_transfer_text: false,
+            deserialize_mode: DeserializeMode::default(),
+            language: options.language,
+        }
+    }
+}
+
+pub struct Client {
+    caller: CallerWrapper,
+    fee_schedule: FeeSchedule,
+    config: ClientConfig,
+    token_refresh: Option<TokenRefresh>,
+}
+
+impl Client {
+    pub fn new<T: Display>(token: Option<T>) -> Self {
+        Self::try_new(token).unwrap()
+    }
+
+    /// Client configured from environment variables:
+    ///
+    /// - `YOOMONEY_TOKEN`: access token, required
+    /// - `YOOMONEY_BASE_URL`: API address, [`DEFAULT_ADDR`] if unset
+    /// - `YOOMONEY_REQUEST_TIMEOUT_SECS`, `YOOMONEY_CONNECT_TIMEOUT_SECS`: timeouts in seconds
+    /// - `YOOMONEY_MAX_RETRIES`: retries of failed requests with the default backoff
+    /// - `YOOMONEY_MAX_CONCURRENT_REQUESTS`: limit of requests in flight
+    /// - `YOOMONEY_LANGUAGE`: language of server messages
+    ///
+    /// Fails with [`Error::MissingEnv`] naming all missing required variables, or with
+    /// [`Error::InvalidEnv`] if a value can't be parsed.
+    #[allow(clippy::missing_errors_doc)]
+    pub fn from_env() -> Result<Self, Error> {
+        Self::from_env_with(|var| std::env::var(var).ok())
+    }
+
+    /// Same as [`from_env`](Self::from_env), looking variables up with `lookup`.
+    #[allow(clippy::missing_errors_doc)]
+    pub fn from_env_with(lookup: impl Fn(&str) -> Option<String>) -> Result<Self, Error> {
+        fn parse<T: std::str::FromStr>(
+            lookup: &impl Fn(&str) -> Option<String>,
+            var: &'static str,
+        ) -> Result<Option<T>, Error> {
+            lookup(var)
+                .map(|value| {
+                    value
+                        .trim()
+                        .parse()
+                        .map_err(|_| Error::InvalidEnv { var, value })
+                })
+                .transpose()
+        }
+
+        let missing = [ENV_TOKEN]
+            .into_iter()
+            .filter(|var| lookup(var).is_none())
+            .collect::<Vec<_>>();
+        if !missing.is_empty() {
+            return Err(Error::MissingEnv { vars: missing });
+        }
+
+        let mut builder = ClientBuilder::new().token(lookup(ENV_TOKEN).unwrap_or_default());
+        if let Some(addr) = lookup("YOOMONEY_BASE_URL") {
+            builder = builder.addr(addr);
+        }
+        if let Some(secs) = parse(&lookup, "YOOMONEY_REQUEST_TIMEOUT_SECS")? {
+            builder = builder.request_timeout(Duration::from_secs(secs));
+        }
+        if let Some(secs) = parse(&lookup, "YOOMONEY_CONNECT_TIMEOUT_SECS")? {
+            builder = builder.connect_timeout(Duration::from_secs(secs));
+        }
+        if let Some(max_retries) = parse(&lookup, "YOOMONEY_MAX_RETRIES")? {
+            builder = builder.retry_policy(RetryPolicy {
+                max_retries,
+                ..RetryPolicy::default()
+            });
+        }
+        if let Some(limit) = parse(&lookup, "YOOMONEY_MAX_CONCURRENT_REQUESTS")? {
+            builder = builder.max_concurrent_requests(limit);
+        }
+        if let Some(language) = lookup("YOOMONEY_LANGUAGE") {
+            builder = builder.language(&language);
+        }
+
+        builder.try_build()
+    }
+
+    #[allow(clippy::missing_errors_doc)]
+    pub fn try_new<T: Display>(token: Option<T>) -> Result<Self, Error> {
+        let mut builder = ClientBuilder::new();
+        if let Some(token) = token {
+            builder = builder.token(token);
+        }
+        builder.try_build()
+    }
+
+    /// Create client on top of a custom transport, e.g. a mock.
+    #[must_use]
+    pub fn from_transport(transport: Arc<dyn Transport>) -> Self {
+        Self {
+            config: ClientConfig::for_transport(transport.as_ref()),
+            caller: CallerWrapper {
+                transport,
+                limiter: None,
+                mode: DeserializeMode::default(),
+            },
+            fee_schedule: FeeSchedule::default(),
+            token_refresh: None,
+        }
+    }
+
+    /// Switch response parsing mode, e.g. of a client made with [`from_transport`](Self::from_transport).
+    #[must_use]
+    pub fn with_deserialize_mode(mut self, mode: DeserializeMode) -> Self {
+        self.caller.mode = mode;
+        self.config.deserialize_mode = mode;
+        self
+    }
+
+    /// When a history stream gets its token rejected, exchange `refresh_token` for new credentials
+    /// with `refresh` and retry the page once, so that long pulls survive token rotation.
+    ///
+    /// `refresh` receives the current refresh token and is the place to persist the new credentials.
+    /// The new access token is used for all subsequent requests, and the new refresh token, if any,
+    /// for the next refresh. If the refresh fails, the stream ends with the original auth error.
+    #[must_use]
+    pub fn with_token_refresh<F, Fut>(mut self, refresh_token: String, refresh: F) -> Self
+    where
+        F: Fn(String) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = anyhow::Result<StoredCredentials>> + Send + 'static,
+    {
+        self.token_refresh = Some(TokenRefresh::new(refresh_token, refresh));
+        self
+    }
+
+    /// Client acting on behalf of another user, sharing this client's connection pool and settings.
+    ///
+    /// Requests made through the returned client use `token`, without affecting this one.
+    /// Base URL is copied at this point and not synchronized afterwards.
+    #[allow(clippy::missing_errors_doc)]
+    pub fn with_bearer(&self, token: &str) -> anyhow::Result<Self> {
+        Ok(Self {
+            caller: CallerWrapper {
+                transport: self.caller.transport.with_bearer(token.to_string())?,
+                limiter: self.caller.limiter.clone(),
+                mode: self.caller.mode,
+            },
+            fee_schedule: self.fee_schedule.clone(),
+            config: self.config.clone(),
+            // Refresh token belongs to this client's token, not to `token`
+            token_refresh: None,
+        })
+    }
+
+    /// Fee rates for estimating transfer costs without a network call.
+    #[must_use]
+    pub fn fee_schedule(&self) -> &FeeSchedule {
+        &self.fee_schedule
+    }
+
+    #[must_use]
+    pub fn builder() -> ClientBuilder {
+        ClientBuilder::new()
+    }
+
+    /// Settings this client was built with, e.g. for logging them at startup.
+    #[must_use]
+    pub fn config(&self) -> &ClientConfig {
+        &self.config
+    }
+
+    #[must_use]
+    pub fn base_url(&self) -> Option<String> {
+        self.caller.transport.base_url()
+    }
+
+    /// Wallet and linked cards available for funding payments, for display outside the payment flow.
+    pub async fn linked_money_sources(&self) -> anyhow::Result<LinkedMoneySources> {
+        Ok(LinkedMoneySources::from(&self.account_info().await?))
+    }
+
+    /// Send subsequent requests to another base address, e.g. for failover.
+    ///
+    /// Requests already in flight keep using the previous address.
+    pub fn set_base_url(&self, url: String) -> anyhow::Result<()> {
+        self.caller.transport.set_base_url(url)
+    }
+
+    /// Whether requests go to the testing environment rather than production.
+    ///
+    /// True if built with [`ClientBuilder::sandbox`] or if the base URL points at [`SANDBOX_ADDR`].
+    #[must_use]
+    pub fn is_sandbox(&self) -> bool {
+        self.config.sandbox
+            || self
+                .base_url()
+                .is_some_and(|url| url.trim_end_matches('/') == SANDBOX_ADDR)
+    }
+
+    /// Call an arbitrary API endpoint, reporting latency and response size alongside the result.
+    pub async fn call_timed<T>(
+        &self,
+        method: &'static str,
+        params: &HashMap<&str, String>,
+    ) -> anyhow::Result<Timed<T>>
+    where
+        T: for<'de> serde::Deserialize<'de> + Send + 'static,
+    {
+        let Timed { value, stats } = self.caller.call_timed(method, params).await?;
+
+        Ok(Timed {
+            value: value.into_result()?,
+            stats,
+        })
+    }
+
+    /// Protected incoming transfers that wait for this user to accept them.
+    pub async fn pending_incoming_transfers(&self) -> anyhow::Result<Vec<Operation>> {
+        self.operation_history(
+            hashset! { ReqOperationType::IncomingTransfersUnaccepted },
+            None,
+            None,
+            None,
+            0,
+            false,
+            false,
+        )
+        .collect()
+        .await
+    }
+
+    /// Operations of all types newer than `last_seen`, for incremental sync.
+    #[must_use]
+    pub fn operation_history_until(
+        &self,
+        last_seen: String,
+        details: bool,
+    ) -> ResultStream<Operation> {
+        self.operation_history(Default::default(), None, None, None, 0, details, false)
+            .until_operation(last_seen)
+    }
+
+    /// Fetch one page of history starting at `start_record`, without following `next_record`.
+    /// For custom pagination, such as fetching pages in parallel.
+    pub async fn operation_history_page(
+        &self,
+        filter: &HistoryFilter,
+        start_record: u64,
+    ) -> anyhow::Result<OperationHistoryResponse> {
+        let mut params = history_params(
+            &filter.operation_types,
+            filter.label.clone(),
+            filter.range.from,
+            filter.range.till,
+            filter.details,
+        );
+        params.insert("start-record", start_record.to_string());
+
         self.caller
-            .call("api/request-payment", &params)
+            .call::<OperationHistoryResponse>("api/operation-history", &params)
+            .await?
+            .into_result()
+    }
+
+    /// Count and total of successful operations in `range` per type, in a single pass over history.
+    /// Totals are signed, so outgoing operations count negative.
+    pub async fn operation_type_breakdown(
+        &self,
+        range: DateRange,
+    ) -> anyhow::Result<HashMap<RspOperationType, (u64, BigDecimal)>> {
+        let mut operations = self.operation_history(
+            Default::default(),
+            None,
+            range.from,
+            range.till,
+            0,
+            false,
+            false,
+        );
+
+        let mut breakdown = HashMap::<_, (u64, BigDecimal)>::new();
+        while let Some(op) = operations.next().await {
+            let op = op?;
+            if !matches!(op.status, OperationStatus::Success) {
+                continue;
+            }
+            let (count, total) = breakdown.entry(op.operation_type).or_default();
+            *count += 1;
+            *total += op.signed_amount();
+        }
+
+        Ok(breakdown)
+    }
+
+    /// Write history matching `filter` to `writer` as newline-delimited JSON, one operation per line,
+    /// without buffering it in memory. Returns the number of operations written.
+    ///
+    /// The writer is flushed every [`NDJSON_FLUSH_EVERY`] lines and at the end. The first history or
+    /// write error stops the export and is returned; lines written before it stay in place.
+    pub async fn operation_history_to_writer<W>(
+        &self,
+        filter: &HistoryFilter,
+        writer: &mut W,
+    ) -> anyhow::Result<u64>
+    where
+        W: AsyncWrite + Unpin,
+    {
+        let mut operations = self.operation_history(
+            filter.operation_types.clone(),
+            filter.label.clone(),
+            filter.range.from,
+            filter.range.till,
+            0,
+            filter.details,
+            false,
+        );
+
+        let mut written = 0;
+        while let Some(op) = operations.next().await {
+            let mut line = serde_json::to_vec(&op?)?;
+            line.push(b'\n');
+            writer.write_all(&line).await?;
+            written += 1;
+            if written % NDJSON_FLUSH_EVERY == 0 {
+                writer.flush().await?;
+            }
+        }
+        writer.flush().await?;
+
+        Ok(written)
+    }
+
+    /// Poll history every `poll_interval` until a successful deposit with `label` shows up, giving up
+    /// with `None` after `timeout`. Dropping the future stops polling.
+    pub async fn await_deposit(
+        &self,
+        label: &str,
+        timeout: Duration,
+        poll_interval: Duration,
+    ) -> anyhow::Result<Option<Operation>> {
+        let mut params = history_params(
+            &hashset! { ReqOperationType::Deposition },
+            Some(label.to_string()),
+            None,
+            None,
+            false,
+        );
+        params.insert("start-record", "0".into());
+
+        let poll = async {
+            loop {
+                let rsp = self
+                    .caller
+                    .call::<OperationHistoryResponse>("api/operation-history", &params)
+                    .await?
+                    .into_result()?;
+                if let Some(op) = rsp.operations.into_iter().find(|op| {
+                    // Unlabelled deposits belong to someone else, whatever the filter returned
+                    matches!(op.status, OperationStatus::Success)
+                        && op.label.as_deref() == Some(label)
+                }) {
+                    return Ok(op);
+                }
+                tokio::time::sleep(poll_interval).await;
+            }
+        };
+
+        match tokio::time::timeout(timeout, poll).await {
+            Ok(res) => res.map(Some),
+            Err(_) => Ok(None),
+        }
+    }
+
+    /// Call an arbitrary API endpoint, returning response headers alongside the result.
+    pub async fn call_with_context<T>(
+        &self,
+        method: &'static str,
+        params: &HashMap<&str, String>,
+    ) -> anyhow::Result<(T, CallContext)>
+    where
+        T: for<'de> serde::Deserialize<'de> + Send + 'static,
+    {
+        let (rsp, context) = self.caller.call_with_context(method, params).await?;
+
+        Ok((rsp.into_result()?, context))
+    }
+
+    /// Look up recipient status, e.g. to show "sending to: identified user" before the actual transfer.
+    ///
+    /// This requests a minimal transfer which is never processed, so it leaves a pending payment
+    /// request behind that expires on its own.
+    pub async fn resolve_recipient(&self, to: UserId) -> anyhow::Result<RecipientInfo> {
+        let recipient_type = to.recipient_type();
+        let rsp = self
+            .request_transfer(
+                to,
+                RequestAmount::Total(BigDecimal::new(1.into(), 2)),
+                String::new(),
+                String::new(),
+                None,
+                false,
+                None,
+                false,
+                0,
+            )
+            .send()
+            .await?;
+        let (_, data) = rsp
+            .into_result()
+            .map_err(|error| format_err!("recipient check refused: {error}"))?;
+
+        Ok(RecipientInfo {
+            recipient_type,
+            account_status: data.recipient_account_status,
+            account_type: data.recipient_account_type,
+        })
+    }
+
+    /// Same as [`API::request_shop_payment`], for patterns with array or nested params, see [`ShopParam`].
+    #[must_use]
+    pub fn request_shop_payment_structured(
+        &self,
+        pattern_id: String,
+        params: BTreeMap<String, ShopParam>,
+    ) -> PaymentRequest {
+        self.request_shop_payment(pattern_id, flatten_shop_params(params))
+    }
+
+    /// Same as [`API::request_shop_payment`], but first checks params against the showcase form constraints.
+    #[allow(clippy::missing_errors_doc)]
+    pub fn request_shop_payment_validated(
+        &self,
+        showcase: &Showcase,
+        pattern_id: String,
+        other: HashMap<String, String>,
+    ) -> Result<PaymentRequest, ShowcaseValidationError> {
+        showcase.validate(&other)?;
+
+        Ok(self.request_shop_payment(pattern_id, other))
+    }
+
+    /// Same as [`API::operation_history`], also returning counters of pages and operations
+    /// fetched by the stream, e.g. for recording how much each sync run pulled.
+    #[allow(clippy::too_many_arguments)]
+    #[must_use]
+    pub fn operation_history_with_stats(
+        &self,
+        operation_types: HashSet<ReqOperationType>,
+        label: Option<String>,
+        from: Option<DateTime<Utc>>,
+        till: Option<DateTime<Utc>>,
+        mut start_record: u64,
+        details: bool,
+        ascending: bool,
+    ) -> (ResultStream<Operation>, HistoryStats) {
+        let caller = self.caller.clone();
+        let token_refresh = self.token_refresh.clone();
+        let mut params = history_params(&operation_types, label, from, till, details);
+
+        let stats = HistoryStats::default();
+        stats.record_resume(Some(start_record));
+        let counters = stats.clone();
+        let stream = Box::pin(stream! {
+            let mut buffered = Vec::new();
+            loop {
+                params.insert("start-record", start_record.to_string());
+
+                let mut refreshed = false;
+                let rsp = loop {
+                    let res = caller
+                        .call::<OperationHistoryResponse<serde_json::Value>>("api/operation-history", &params)
+                        .await;
+                    if let Some(token_refresh) = token_refresh.as_ref().filter(|_| !refreshed && is_auth_rejection(&res)) {
+                        refreshed = true;
+                        match token_refresh.run(caller.transport.as_ref()).await {
+                            Ok(()) => continue,
+                            Err(e) => tracing::warn!(error = %e, "Token refresh failed"),
+                        }
+                    }
+                    break res.and_then(Rsp::into_result);
+                };
+                let rsp = match rsp {
+                    Ok(rsp) => rsp,
+                    Err(e) => {
+                        yield Err(e);
+                        return;
+                    }
+                };
+
+                let next_record = rsp.next_record.as_ref().map(|v| v.0);
+                counters.record_page(next_record);
+                if rsp.operations.is_empty() {
+                    break;
+                }
+
+                for op in rsp.operations {
+                    // Parse operations one by one so that a single malformed entry does not abort the whole page
+                    let op = serde_json::from_value::<Operation>(op.clone()).map_err(|source| {
+                        Error::Deserialize {
+                            endpoint: "api/operation-history",
+                            source,
+                            body: redact_body(&op.to_string()),
+                        }
+                        .into()
+                    });
+                    if ascending {
+                        buffered.push(op);
+                    } else {
+                        if op.is_ok() {
+                            counters.record_operation();
+                        }
+                        yield op;
+                    }
+                }
+
+                // Reached only once the consumer asked for more after the last operation of the page
+                if !ascending {
+                    counters.record_resume(next_record);
+                }
+
+                match next_record {
+                    Some(v) => {
+                        start_record = v;
+                    }
+                    None => {
+                        break;
+                    }
+                }
+            }
+
+            for op in buffered.into_iter().rev() {
+                if op.is_ok() {
+                    counters.record_operation();
+                }
+                yield op;
+            }
+            counters.record_resume(None);
+        });
+
+        (stream, stats)
+    }
+
+    /// Estimate how many pages pulling the history matching the filter would take, at the cost of a single request.
+    ///
+    /// The API reports no total count, so this is an extrapolation: if the first page is not the last one
+    /// and `range` has a start, the page count is scaled by how much of the range the first page covered.
+    /// Without a start of the range, the page count is left unknown. Either way, it is only an estimate,
+    /// as operations are rarely spread evenly over time.
+    pub async fn estimate_history_pages(
+        &self,
+        operation_types: &HashSet<ReqOperationType>,
+        label: Option<String>,
+        range: DateRange,
+    ) -> anyhow::Result<HistoryEstimate> {
+        let mut params = history_params(operation_types, label, range.from, range.till, false);
+        params.insert("start-record", "0".into());
+        let rsp = self
+            .caller
+            .call::<OperationHistoryResponse>("api/operation-history", &params)
+            .await?
+            .into_result()?;
+
+        let first_page_size = rsp.operations.len();
+        let pages = if rsp.next_record.is_none() {
+            Some(u64::from(first_page_size > 0))
+        } else {
+            let newest = rsp.operations.iter().map(|op| op.datetime).max();
+            let oldest = rsp.operations.iter().map(|op| op.datetime).min();
+            match (range.from, newest, oldest) {
+                (Some(from), Some(newest), Some(oldest)) if oldest > from && newest > oldest => {
+                    let seconds = |d: chrono::Duration| u64::try_from(d.num_seconds()).unwrap_or(0);
+                    let covered = seconds(newest - oldest).max(1);
+                    Some(seconds(newest - from).div_ceil(covered).max(2))
+                }
+                _ => None,
+            }
+        };
+
+        Ok(HistoryEstimate {
+            first_page_size,
+            pages,
+        })
+    }
+
+    /// Run a separate paginated query per operation type concurrently and merge the results,
+    /// tagging each operation with the type it was found under. Operations returned for several
+    /// types are yielded once.
+    ///
+    /// Every type costs its own series of requests, so this uses more quota than a single
+    /// [`API::operation_history`] call with all types combined.
+    #[must_use]
+    pub fn operation_history_per_type(
+        &self,
+        operation_types: HashSet<ReqOperationType>,
+        from: Option<DateTime<Utc>>,
+        till: Option<DateTime<Utc>>,
+        details: bool,
+    ) -> ResultStream<(ReqOperationType, Operation)> {
+        let mut streams = StreamMap::new();
+        for ty in operation_types {
+            streams.insert(
+                ty,
+                self.operation_history(hashset! { ty }, None, from, till, 0, details, false),
+            );
+        }
+
+        Box::pin(stream! {
+            let mut seen = HashSet::new();
+            while let Some((ty, res)) = streams.next().await {
+                match res {
+                    Ok(op) => {
+                        if seen.insert(op.operation_id.clone()) {
+                            yield Ok((ty, op));
+                        }
+                    }
+                    Err(e) => yield Err(e),
+                }
+            }
+        })
+    }
+
+    /// Poll the balance every `interval`, yielding it each time, or with `distinct` only when it changed.
+    ///
+    /// The API has no push notifications for balance, so this is plain polling: every tick is an
+    /// `account_info` request counted against the rate limit. Failed polls are yielded as errors and
+    /// polling continues. Polling stops when the stream is dropped.
+    #[must_use]
+    pub fn balance_stream(&self, interval: Duration, distinct: bool) -> ResultStream<BigDecimal> {
+        let caller = self.caller.clone();
+
+        Box::pin(stream! {
+            let mut ticks = tokio::time::interval(interval);
+            ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            let mut last = None;
+            loop {
+                ticks.tick().await;
+                let balance = match caller
+                    .call::<AccountInfo>("api/account-info", &Default::default())
+                    .await
+                    .and_then(Rsp::into_result)
+                {
+                    Ok(info) => info.balance,
+                    Err(e) => {
+                        yield Err(e);
+                        continue;
+                    }
+                };
+                if distinct && last.as_ref() == Some(&balance) {
+                    continue;
+                }
+                last = Some(balance.clone());
+                yield Ok(balance);
+            }
+        })
+    }
+
+    /// Pair every operation with its details, fetching up to [`ClientBuilder::details_concurrency`]
+    /// of them at a time. Order of `operations` is kept and their errors are passed through.
+    ///
+    /// Details requests go through this client, so with [`ClientBuilder::max_concurrent_requests`] set,
+    /// they share the limit with history pages and any other calls, keeping the combined volume within quota.
+    #[must_use]
+    pub fn with_details_fetch(
+        &self,
+        mut operations: ResultStream<Operation>,
+    ) -> ResultStream<(Operation, OperationDetails)> {
+        let caller = self.caller.clone();
+        let concurrency = self.config.details_concurrency.max(1);
+
+        Box::pin(stream! {
+            let mut pending = VecDeque::new();
+            let mut exhausted = false;
+            loop {
+                while !exhausted && pending.len() < concurrency {
+                    let task = match operations.next().await {
+                        Some(Ok(op)) => {
+                            let details = caller.call::<OperationDetails>(
+                                "api/operation-details",
+                                &hashmap! { "operation_id" => op.operation_id.clone() },
+                            );
+                            tokio::spawn(async move { Ok((op, details.await?.into_result()?)) })
+                        }
+                        Some(Err(e)) => tokio::spawn(async move { Err(e) }),
+                        None => {
+                            exhausted = true;
+                            break;
+                        }
+                    };
+                    pending.push_back(task);
+                }
+
+                match pending.pop_front() {
+                    Some(task) => match task.await {
+                        Ok(res) => yield res,
+                        Err(e) => std::panic::resume_unwind(e.into_panic()),
+                    },
+                    None => break,
+                }
+            }
+        })
+    }
+
+    /// Send a payment request and, unless it is refused, process it with `money_source` right away.
+    pub async fn request_and_process(
+        &self,
+        request: impl PaymentRequestTrait,
+        money_source: ProcessPaymentMoneySource,
+    ) -> anyhow::Result<ProcessPaymentResponse> {
+        let (_, data) = request
+            .send()
             .await?
             .into_result()
+            .map_err(|error| format_err!("payment request refused: {error}"))?;
+
+        self.process_payment(data.request_id, money_source).await
+    }
+
+    /// Process a payment without risking a double charge, waiting out `in_progress` responses.
+    ///
+    /// `process-payment` is not safe to retry blindly: if a call fails after being sent, e.g. the
+    /// connection dropped before the response arrived, the payment may or may not have gone through.
+    /// The API pays at most once per `request_id` and answers repeated calls with the same id with
+    /// the status of that payment, so on such a failure this polls by `request_id` instead of
+    /// starting over. Never retry by sending a new `request-payment`: that creates a new payment.
+    ///
+    /// Only network failures and maintenance pages leave the outcome unknown; any other error is
+    /// returned right away. Gives up after `max_attempts` calls, returning the last error or
+    /// `in_progress` response.
+    pub async fn process_payment_safe(
+        &self,
+        request_id: String,
+        money_source: ProcessPaymentMoneySource,
+        max_attempts: u32,
+    ) -> anyhow::Result<ProcessPaymentResponse> {
+        // Fail on invalid input before anything is sent, rather than polling on it
+        money_source.validate()?;
+
+        let backoff = RetryPolicy::default();
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            let retry_in = match self
+                .process_payment(request_id.clone(), money_source.clone())
+                .await
+            {
+                Ok(rsp) if attempt < max_attempts => match rsp.next_retry_duration() {
+                    Some(delay) => delay,
+                    None => return Ok(rsp),
+                },
+                Err(e) if attempt < max_attempts && is_outcome_unknown(&e) => {
+                    tracing::warn!(attempt, %e, "Outcome of process-payment unknown, polling by request id");
+                    backoff.backoff(attempt)
+                }
+                res => return res,
+            };
+            tokio::time::sleep(retry_in).await;
+        }
+    }
+
+    /// Request many transfers, at most `concurrency` at a time.
+    ///
+    /// A failed transfer does not abort the batch: each spec is returned with its own result, in input order.
+    pub async fn batch_transfer(
+        &self,
+        transfers: Vec<TransferSpec>,
+        concurrency: usize,
+    ) -> Vec<(TransferSpec, anyhow::Result<RequestPaymentResponse>)> {
+        let semaphore = Arc::new(Semaphore::new(concurrency.max(1)));
+        let mut tasks = JoinSet::new();
+        for (i, spec) in transfers.iter().cloned().enumerate() {
+            let request = self.request_transfer(
+                spec.to,
+                spec.amount,
+                spec.comment,
+                spec.message,
+                spec.label,
+                spec.codepro,
+                spec.protection_code,
+                spec.hold_for_pickup,
+                spec.expire_period,
+            );
+            let semaphore = semaphore.clone();
+            tasks.spawn(async move {
+                let _permit = semaphore.acquire_owned().await;
+                (i, request.send().await)
+            });
+        }
+
+        let mut results = transfers
+            .into_iter()
+            .map(|spec| (spec, None))
+            .collect::<Vec<_>>();
+        while let Some(res) = tasks.join_next().await {
+            match res {
+                Ok((i, res)) => results[i].1 = Some(res),
+                Err(e) => std::panic::resume_unwind(e.into_panic()),
+            }
+        }
+
+        results
+            .into_iter()
+            .map(|(spec, res)| (spec, res.expect("every task reports its result; qed")))
+            .collect()
+    }
+
+    /// Revoke the token of this client.
+    ///
+    /// A token that is already revoked or otherwise invalid counts as revoked, so that logging out
+    /// can be repeated safely. Network and other errors are still returned.
+    pub async fn revoke_token(self) -> anyhow::Result<()> {
+        match self
+            .caller
+            .call_empty("api/revoke", &Default::default())
+            .await
+        {
+            Err(e) if matches!(e.downcast_ref(), Some(Error::InvalidToken { .. })) => {
+                tracing::debug!("Token was already invalid");
+                Ok(())
+            }
+            res => res,
+        }
     }
 }
 
-pub struct TestPaymentRequest {
-    inner: PaymentRequest,
+/// Client that revokes its token when dropped.
+///
+/// Revocation on drop is best effort: it is spawned on the current Tokio runtime, so it is skipped
+/// when dropped outside of one, may not complete if the runtime shuts down first, and its errors
+/// are only logged. Call [`revoke`](Self::revoke) to revoke deterministically.
+pub struct ScopedClient {
+    inner: Option<Client>,
 }
 
-impl From<PaymentRequest> for TestPaymentRequest {
-    fn from(inner: PaymentRequest) -> Self {
-        Self { inner }
+impl From<Client> for ScopedClient {
+    fn from(client: Client) -> Self {
+        Self {
+            inner: Some(client),
+        }
     }
 }
 
-#[async_trait]
-impl PaymentRequestTrait for TestPaymentRequest {
-    async fn send(mut self) -> anyhow::Result<RequestPaymentResponse> {
+impl ScopedClient {
+    pub async fn revoke(mut self) -> anyhow::Result<()> {
         self.inner
-            .params
-            .insert("test_payment".to_string(), true.to_string());
-
-        Ok(self.inner.send().await?)
+            .take()
+            .expect("only taken on revoke or drop; qed")
+            .revoke_token()
+            .await
     }
 }
 
-pub struct Client {
-    caller: CallerWrapper,
-}
+impl std::ops::Deref for ScopedClient {
+    type Target = Client;
 
-impl Client {
-    pub fn new<T: Display>(token: Option<T>) -> Self {
-        let http_client = reqwest::Client::builder().build().unwrap();
-        Self {
-            caller: CallerWrapper {
-                transport: Arc::new(RemoteCaller {
-                    http_client,
-                    addr: "https://money.yandex.ru".into(),
-                    bearer: token.map(|t| t.to_string()),
-                }),
-            },
-        }
+    fn deref(&self) -> &Self::Target {
+        self.inner
+            .as_ref()
+            .expect("only taken on revoke or drop; qed")
     }
+}
 
-    pub async fn revoke_token(self) -> anyhow::Result<()> {
-        self.caller
-            .call_empty("api/revoke", &Default::default())
-            .await
+impl Drop for ScopedClient {
+    fn drop(&mut self) {
+        if let Some(client) = self.inner.take() {
+            match tokio::runtime::Handle::try_current() {
+                Ok(handle) => {
+                    handle.spawn(async move {
+                        if let Err(e) = client.revoke_token().await {
+                            tracing::warn!("Failed to revoke token on drop: {e}");
+                        }
+                    });
+                }
+                Err(_) => {
+                    tracing::warn!("No runtime to revoke token on drop, token stays valid");
+                }
+            }
+        }
     }
 }
 
@@ -143,47 +1339,144 @@ pub struct UnauthorizedClient {
 impl UnauthorizedClient {
     #[must_use]
     pub fn new(client_id: String, redirect_uri: String) -> Self {
-        let http_client = reqwest::Client::builder().build().unwrap();
+        ClientBuilder::new().build_unauthorized(client_id, redirect_uri)
+    }
+
+    #[allow(clippy::missing_errors_doc)]
+    pub fn try_new(client_id: String, redirect_uri: String) -> Result<Self, Error> {
+        ClientBuilder::new().try_build_unauthorized(client_id, redirect_uri)
+    }
+
+    /// Create client on top of a custom transport, e.g. a mock.
+    #[must_use]
+    pub fn from_transport(
+        transport: Arc<dyn Transport>,
+        client_id: String,
+        redirect_uri: String,
+    ) -> Self {
         Self {
             caller: CallerWrapper {
-                transport: Arc::new(RemoteCaller {
-                    http_client,
-                    addr: "https://money.yandex.ru".into(),
-                    bearer: None,
-                }),
+                transport,
+                limiter: None,
+                mode: DeserializeMode::default(),
             },
             client_id,
             redirect_uri,
         }
     }
 
-    pub async fn authorize<F, Fut>(
+    /// Run the OAuth flow. `authorize_callback` receives the address to open in a browser and returns
+    /// the [`AuthorizationResponse`] from the redirect, whose `state` has to match the one sent.
+    pub async fn authorize<F, Fut, R>(
         self,
         access_scope: HashSet<AccessScope>,
         authorize_callback: F,
     ) -> anyhow::Result<String>
     where
         F: Fn(String) -> Fut + Send,
-        Fut: Future<Output = anyhow::Result<String>> + Send,
+        Fut: Future<Output = anyhow::Result<R>> + Send,
+        R: Into<AuthorizationResponse>,
+    {
+        self.authorize_with_options(access_scope.into(), authorize_callback)
+            .await
+    }
+
+    pub async fn authorize_with_options<F, Fut, R>(
+        self,
+        options: AuthorizeOptions,
+        authorize_callback: F,
+    ) -> anyhow::Result<String>
+    where
+        F: Fn(String) -> Fut + Send,
+        Fut: Future<Output = anyhow::Result<R>> + Send,
+        R: Into<AuthorizationResponse>,
+    {
+        Ok(self
+            .authorize_credentials(options, authorize_callback)
+            .await?
+            .access_token)
+    }
+
+    /// Authorize again as the same app instance as `previous`, unless `options` name another one.
+    ///
+    /// YooMoney ties a token to the `instance_name` it was issued for, and merchants see payments
+    /// made through external-payment instances by that identity. Reusing it keeps one stable
+    /// instance across re-authorizations instead of registering a new one each time.
+    pub async fn reauthorize<F, Fut, R>(
+        self,
+        previous: &StoredCredentials,
+        mut options: AuthorizeOptions,
+        authorize_callback: F,
+    ) -> anyhow::Result<StoredCredentials>
+    where
+        F: Fn(String) -> Fut + Send,
+        Fut: Future<Output = anyhow::Result<R>> + Send,
+        R: Into<AuthorizationResponse>,
+    {
+        if options.instance_name.is_none() {
+            options.instance_name.clone_from(&previous.instance_name);
+        }
+        self.authorize_credentials(options, authorize_callback)
+            .await
+    }
+
+    /// Same as [`authorize_with_options`](Self::authorize_with_options), returning credentials that
+    /// remember the `instance_name` and scopes used, ready for [`reauthorize`](Self::reauthorize).
+    pub async fn authorize_credentials<F, Fut, R>(
+        self,
+        mut options: AuthorizeOptions,
+        authorize_callback: F,
+    ) -> anyhow::Result<StoredCredentials>
+    where
+        F: Fn(String) -> Fut + Send,
+        Fut: Future<Output = anyhow::Result<R>> + Send,
+        R: Into<AuthorizationResponse>,
     {
+        let instance_name = options
+            .instance_name
+            .get_or_insert_with(|| Uuid::new_v4().to_string())
+            .clone();
+        let state = options
+            .state
+            .clone()
+            .unwrap_or_else(|| Uuid::new_v4().to_string());
+
+        let mut params = options
+            .extra_params
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.clone()))
+            .collect::<HashMap<_, _>>();
+        params.extend(hashmap! {
+            "client_id" => self.client_id.clone(),
+            "response_type" => "code".to_string(),
+            "redirect_uri" => self.redirect_uri.clone(),
+            "scope" => options.scope_param(),
+            "instance_name" => instance_name.clone(),
+            "state" => state.clone(),
+        });
+
         // Get address to be opened in browser
-        let redirect_addr = self
-            .caller
-            .get_redirect(
-                "oauth/authorize",
-                &hashmap! {
-                    "client_id" => self.client_id.clone(),
-                    "response_type" => "code".to_string(),
-                    "redirect_uri" => self.redirect_uri.clone(),
-                    "scope" => access_scope.iter().map(|s| ron::ser::to_string(s).unwrap()).join(" "),
-                    "instance_name" => Uuid::new_v4().to_string(),
-                },
-            )
-            .await?;
+        let redirect_addr = self.caller.get_redirect("oauth/authorize", &params).await?;
 
         // This should open the page in browser
         let temp_token_fut = authorize_callback(redirect_addr);
-        let temp_token = temp_token_fut.await?;
+        let AuthorizationResponse {
+            code: temp_token,
+            state: returned_state,
+        } = temp_token_fut.await?.into();
+
+        // A redirect without `state` can't be told apart from a forged one
+        match returned_state {
+            None => return Err(Error::StateMissing { expected: state }.into()),
+            Some(returned_state) if returned_state != state => {
+                return Err(Error::StateMismatch {
+                    expected: state,
+                    actual: returned_state,
+                }
+                .into());
+            }
+            Some(_) => {}
+        }
 
         let token = self
             .caller
@@ -199,89 +1492,266 @@ impl UnauthorizedClient {
             .await?
             .into_result()?;
 
-        Ok(token.access_token)
+        Ok(StoredCredentials {
+            access_token: token.access_token,
+            scope: Some(options.scope_param()),
+            instance_name: Some(instance_name),
+            ..Default::default()
+        })
     }
 }
 
-#[async_trait]
-impl API for Client {
-    async fn account_info(&self) -> anyhow::Result<AccountInfo> {
-        Ok(self
-            .caller
+fn history_params(
+    operation_types: &HashSet<ReqOperationType>,
+    label: Option<String>,
+    from: Option<DateTime<Utc>>,
+    till: Option<DateTime<Utc>>,
+    details: bool,
+) -> HashMap<&'static str, String> {
+    let mut params = HashMap::new();
+    params.insert(
+        "types",
+        operation_types.iter().map(ToString::to_string).join(" "),
+    );
+    if let Some(label) = label {
+        params.insert("label", label);
+    }
+    if let Some(v) = from {
+        params.insert("from", v.to_rfc3339());
+    }
+    if let Some(v) = till {
+        params.insert("till", v.to_rfc3339());
+    }
+    params.insert("details", details.to_string());
+
+    params
+}
+
+/// Inherent counterparts of the async [`API`] methods, which don't box the returned futures.
+impl Client {
+    pub async fn account_info(&self) -> anyhow::Result<AccountInfo> {
+        self.caller
             .call("api/account-info", &Default::default())
             .await?
-            .into_result()?)
+            .into_result()
     }
 
-    fn operation_history(
+    /// Accept an incoming transfer. Protected transfers need `protection_code`.
+    ///
+    /// The code is checked to be well-formed before sending, since every wrong attempt
+    /// counts against the limit set by the sender.
+    pub async fn accept_incoming_transfer(
         &self,
-        operation_types: HashSet<ReqOperationType>,
-        label: Option<String>,
-        from: Option<DateTime<Utc>>,
-        till: Option<DateTime<Utc>>,
-        mut start_record: u64,
-        details: bool,
-    ) -> Pin<Box<dyn Stream<Item = anyhow::Result<Operation>> + Send>> {
-        let caller = self.caller.clone();
-        let mut params = HashMap::new();
-        params.insert(
-            "types",
-            operation_types
-                .iter()
-                .map(|v| serde_json::to_string(v).unwrap())
-                .collect::<Vec<_>>()
-                .join(" "),
-        );
-        if let Some(label) = label {
-            params.insert("label", label);
+        operation_id: String,
+        protection_code: Option<&str>,
+    ) -> anyhow::Result<IncomingTransferAcceptResponse> {
+        let mut params = hashmap! { "operation_id" => operation_id };
+        if let Some(code) = protection_code {
+            params.insert(
+                "protection_code",
+                code.parse::<ProtectionCode>()?.to_string(),
+            );
         }
-        if let Some(v) = from {
-            params.insert("from", v.to_rfc3339());
+
+        self.caller
+            .call_plain("api/incoming-transfer-accept", &params)
+            .await
+    }
+
+    /// Same as [`accept_incoming_transfer`](Self::accept_incoming_transfer), keeping count of protection
+    /// code attempts in `attempts` and refusing to try again once none are left.
+    pub async fn accept_protected_transfer(
+        &self,
+        attempts: &mut ProtectionCodeAttempts,
+        protection_code: &str,
+    ) -> anyhow::Result<IncomingTransferAcceptResponse> {
+        if attempts.is_exhausted() {
+            return Err(Error::AttemptsExhausted {
+                operation_id: attempts.operation_id.clone(),
+            }
+            .into());
         }
-        if let Some(v) = till {
-            params.insert("till", v.to_rfc3339());
+
+        let rsp = self
+            .accept_incoming_transfer(attempts.operation_id.clone(), Some(protection_code))
+            .await?;
+        attempts.update(&rsp);
+
+        Ok(rsp)
+    }
+
+    pub async fn operation_details(
+        &self,
+        operation_id: String,
+    ) -> anyhow::Result<OperationDetails> {
+        self.caller
+            .call(
+                "api/operation-details",
+                &hashmap! { "operation_id" => operation_id },
+            )
+            .await?
+            .into_result()
+    }
+
+    /// Fetch details of a recent operation, repeating every `interval` up to `retries` more times while
+    /// it is still in progress or the request fails. The last result is returned once retries run out.
+    pub async fn operation_details_settled(
+        &self,
+        operation_id: String,
+        retries: u32,
+        interval: Duration,
+    ) -> anyhow::Result<OperationDetails> {
+        let mut attempt = 0;
+        loop {
+            let res = self.operation_details(operation_id.clone()).await;
+            let settled = res
+                .as_ref()
+                .is_ok_and(|details| !matches!(details.status, OperationStatus::InProgress));
+            if settled || attempt >= retries {
+                return res;
+            }
+            attempt += 1;
+            tokio::time::sleep(interval).await;
         }
-        params.insert("details", details.to_string());
+    }
 
-        Box::pin(try_stream! {
-            loop {
-                params.insert("start-record", start_record.to_string());
+    pub async fn process_payment(
+        &self,
+        request_id: String,
+        money_source: ProcessPaymentMoneySource,
+    ) -> anyhow::Result<ProcessPaymentResponse> {
+        self.process_payment_with(request_id, money_source, None)
+            .await
+    }
 
-                let rsp = caller
-                    .call::<OperationHistoryResponse>("api/operation-history", &params)
-                    .await?;
+    /// Same as [`process_payment`](Self::process_payment), but fails with [`Error::RequestExpired`]
+    /// without a request if `request` is past its validity window.
+    pub async fn process_requested_payment(
+        &self,
+        request: &RequestPaymentSuccessData,
+        money_source: ProcessPaymentMoneySource,
+    ) -> anyhow::Result<ProcessPaymentResponse> {
+        if let Some(valid_until) = request
+            .request_valid_until
+            .filter(|_| request.is_request_expired())
+        {
+            return Err(Error::RequestExpired {
+                request_id: request.request_id.clone(),
+                valid_until,
+            }
+            .into());
+        }
 
-                    let rsp = rsp.into_result()?;
+        self.process_payment(request.request_id.clone(), money_source)
+            .await
+    }
 
-                if rsp.operations.is_empty() {
-                    return;
-                }
+    /// Process a payment requested with [`TestPaymentRequest`], sending `test_payment` along with the
+    /// test card and result of `test`, if any.
+    pub async fn process_test_payment(
+        &self,
+        request_id: String,
+        money_source: ProcessPaymentMoneySource,
+        test: &TestPayment,
+    ) -> anyhow::Result<ProcessPaymentResponse> {
+        self.process_payment_with(request_id, money_source, Some(test))
+            .await
+    }
 
-                for op in rsp.operations {
-                    yield op;
-                }
+    async fn process_payment_with(
+        &self,
+        request_id: String,
+        money_source: ProcessPaymentMoneySource,
+        test: Option<&TestPayment>,
+    ) -> anyhow::Result<ProcessPaymentResponse> {
+        money_source.validate()?;
 
-                match rsp.next_record {
-                    Some(v) => {
-                        start_record = v.0;
-                    }
-                    None => {
-                        return;
-                    }
+        let mut params = HashMap::new();
+        params.insert("request_id", request_id);
+        match money_source {
+            ProcessPaymentMoneySource::Wallet => {
+                params.insert("money_source", "wallet".into());
+            }
+            ProcessPaymentMoneySource::Card { id, secure3d, csc } => {
+                params.insert("money_source", id);
+                if let Some(csc) = csc {
+                    params.insert("csc", csc);
+                }
+                if let Some(data) = secure3d {
+                    params.insert("ext_auth_success_uri", data.ext_auth_success_uri);
+                    params.insert("ext_auth_fail_uri", data.ext_auth_fail_uri);
                 }
             }
-        })
+        }
+        if let Some(test) = test {
+            params.insert("test_payment", true.to_string());
+            if let Some(card) = &test.card {
+                params.insert("test_card", card.as_param().to_string());
+            }
+            if let Some(result) = &test.result {
+                params.insert("test_result", result.as_param().to_string());
+            }
+        }
+
+        self.caller
+            .call("api/process-payment", &params)
+            .await?
+            .into_result()
+    }
+}
+
+/// Whether a call may have reached the server without its response reaching us.
+///
+/// Anything else, e.g. a rejected token or a response that fails to parse, would fail the same way again.
+fn is_outcome_unknown(e: &anyhow::Error) -> bool {
+    if let Some(e) = e.downcast_ref::<reqwest::Error>() {
+        return !e.is_builder();
+    }
+    matches!(
+        e.downcast_ref(),
+        Some(Error::Maintenance { .. } | Error::Io(_))
+    )
+}
+
+/// Whether the token was rejected, either by HTTP status or in the response body.
+fn is_auth_rejection<T>(res: &anyhow::Result<Rsp<T>>) -> bool {
+    match res {
+        Ok(Rsp::Error { error }) => error == "authorization_reject" || error == "invalid_token",
+        Ok(Rsp::OK(_)) => false,
+        Err(e) => matches!(e.downcast_ref(), Some(Error::InvalidToken { .. })),
+    }
+}
+
+#[async_trait]
+impl API for Client {
+    async fn account_info(&self) -> anyhow::Result<AccountInfo> {
+        Client::account_info(self).await
+    }
+
+    fn operation_history(
+        &self,
+        operation_types: HashSet<ReqOperationType>,
+        label: Option<String>,
+        from: Option<DateTime<Utc>>,
+        till: Option<DateTime<Utc>>,
+        start_record: u64,
+        details: bool,
+        ascending: bool,
+    ) -> Pin<Box<dyn Stream<Item = anyhow::Result<Operation>> + Send>> {
+        self.operation_history_with_stats(
+            operation_types,
+            label,
+            from,
+            till,
+            start_record,
+            details,
+            ascending,
+        )
+        .0
     }
 
     async fn operation_details(&self, operation_id: String) -> anyhow::Result<OperationDetails> {
-        Ok(self
-            .caller
-            .call(
-                "api/operation-details",
-                &hashmap! { "operation_id" => operation_id },
-            )
-            .await?
-            .into_result()?)
+        Client::operation_details(self, operation_id).await
     }
 
     fn request_shop_payment(
@@ -289,15 +1759,19 @@ impl API for Client {
         pattern_id: String,
         other: HashMap<String, String>,
     ) -> PaymentRequest {
-        let mut params = HashMap::new();
+        // Reserved keys are set by the library only, so that e.g. a test payment can't be turned into a real one
+        let invalid = other
+            .keys()
+            .find(|k| RESERVED_SHOP_PARAMS.contains(&k.as_str()))
+            .map(|key| Error::ReservedParam { key: key.clone() });
+
+        let mut params = other;
         params.insert("pattern_id".to_string(), pattern_id);
-        for (k, v) in other {
-            params.insert(k, v);
-        }
 
         PaymentRequest {
             caller: self.caller.clone(),
             params,
+            invalid,
         }
     }
 
@@ -310,12 +1784,26 @@ impl API for Client {
         message: String,
         label: Option<String>,
         codepro: bool,
+        protection_code: Option<ProtectionCode>,
         hold_for_pickup: bool,
         expire_period: u32,
     ) -> PaymentRequest {
+        let mut invalid = None;
+        let mut limit = |field, value, max| {
+            limit_length(field, value, max, self.config.truncate_transfer_text).unwrap_or_else(
+                |e| {
+                    invalid.get_or_insert(e);
+                    String::new()
+                },
+            )
+        };
+        let comment = limit("comment", comment, MAX_COMMENT_LEN);
+        let message = limit("message", message, MAX_MESSAGE_LEN);
+
         let mut params = hashmap! {
             "pattern_id" => "p2p".into(),
             "to" => to.to_string(),
+            "identifier_type" => to.recipient_type().to_string(),
             "comment" => comment,
             "message" => message,
             "codepro" => codepro.to_string(),
@@ -336,12 +1824,19 @@ impl API for Client {
             params.insert("label", v);
         }
 
+        if codepro {
+            if let Some(v) = protection_code {
+                params.insert("protection_code", v.to_string());
+            }
+        }
+
         PaymentRequest {
             caller: self.caller.clone(),
             params: params
                 .into_iter()
                 .map(|(k, v)| (k.to_string(), v))
                 .collect(),
+            invalid,
         }
     }
 
@@ -362,6 +1857,7 @@ impl API for Client {
                 .into_iter()
                 .map(|(k, v)| (k.to_string(), v))
                 .collect(),
+            invalid: None,
         }
     }
 
@@ -370,25 +1866,6 @@ impl API for Client {
         request_id: String,
         money_source: ProcessPaymentMoneySource,
     ) -> anyhow::Result<ProcessPaymentResponse> {
-        let mut params = HashMap::new();
-        params.insert("request_id", request_id);
-        match money_source {
-            ProcessPaymentMoneySource::Wallet => {
-                params.insert("money_source", "wallet".into());
-            }
-            ProcessPaymentMoneySource::Card { id, secure3d } => {
-                params.insert("money_source", id);
-                if let Some(data) = secure3d {
-                    params.insert("ext_auth_success_uri", data.ext_auth_success_uri);
-                    params.insert("ext_auth_fail_uri", data.ext_auth_fail_uri);
-                }
-            }
-        }
-
-        Ok(self
-            .caller
-            .call("api/process-payment", &params)
-            .await?
-            .into_result()?)
+        Client::process_payment(self, request_id, money_source).await
     }
 }
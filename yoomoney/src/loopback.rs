@@ -0,0 +1,115 @@
+use crate::{AuthorizationResponse, Error};
+use std::{future::Future, net::SocketAddr, time::Duration};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+use tracing::*;
+
+/// Local HTTP server receiving the OAuth redirect, for use as `redirect_uri`.
+///
+/// Listens until a redirect carrying an authorization code arrives or shutdown is requested.
+/// The port is released as soon as [`capture`](Self::capture) returns, whatever the outcome.
+#[derive(Debug)]
+pub struct LoopbackServer {
+    listener: TcpListener,
+}
+
+impl LoopbackServer {
+    /// Bind to `port` on localhost. Port 0 picks a free one.
+    #[allow(clippy::missing_errors_doc)]
+    pub async fn bind(port: u16) -> Result<Self, Error> {
+        let listener =
+            TcpListener::bind(("127.0.0.1", port))
+                .await
+                .map_err(|source| match source.kind() {
+                    std::io::ErrorKind::AddrInUse => Error::PortInUse { port },
+                    _ => Error::Io(source),
+                })?;
+
+        Ok(Self { listener })
+    }
+
+    #[allow(clippy::missing_errors_doc)]
+    pub fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Address to register as the application's redirect URI.
+    #[allow(clippy::missing_errors_doc)]
+    pub fn redirect_uri(&self) -> std::io::Result<String> {
+        Ok(format!("http://{}/", self.local_addr()?))
+    }
+
+    /// Wait for the redirect, or until `shutdown` completes.
+    #[allow(clippy::missing_errors_doc)]
+    pub async fn capture(
+        self,
+        shutdown: impl Future<Output = ()>,
+    ) -> anyhow::Result<AuthorizationResponse> {
+        tokio::select! {
+            res = self.accept_redirect() => res,
+            () = shutdown => Err(Error::Cancelled.into()),
+        }
+    }
+
+    /// Wait for the redirect for at most `timeout`.
+    #[allow(clippy::missing_errors_doc)]
+    pub async fn capture_with_timeout(
+        self,
+        timeout: Duration,
+    ) -> anyhow::Result<AuthorizationResponse> {
+        self.capture(tokio::time::sleep(timeout)).await
+    }
+
+    async fn accept_redirect(&self) -> anyhow::Result<AuthorizationResponse> {
+        loop {
+            let (mut socket, peer) = self.listener.accept().await?;
+            match Self::handle(&mut socket).await {
+                Ok(Some(res)) => return res,
+                Ok(None) => {}
+                Err(e) => debug!("Ignoring bad request from {peer}: {e}"),
+            }
+        }
+    }
+
+    /// Answer a single request, returning the outcome if it was the redirect.
+    async fn handle(
+        socket: &mut TcpStream,
+    ) -> anyhow::Result<Option<anyhow::Result<AuthorizationResponse>>> {
+        let mut buf = vec![0; 8192];
+        let n = socket.read(&mut buf).await?;
+        let request = String::from_utf8_lossy(&buf[..n]);
+        let path = request
+            .lines()
+            .next()
+            .and_then(|line| line.split_whitespace().nth(1))
+            .unwrap_or("/");
+
+        let (status, body, response) =
+            match AuthorizationResponse::from_redirect_url(&format!("http://localhost{path}")) {
+                Ok(response) => (
+                    "200 OK",
+                    "Authorization complete. You can close this window.",
+                    Some(Ok(response)),
+                ),
+                Err(e) if matches!(e.downcast_ref(), Some(Error::AuthorizationDenied { .. })) => (
+                    "200 OK",
+                    "Authorization denied. You can close this window.",
+                    Some(Err(e)),
+                ),
+                Err(_) => ("404 Not Found", "Not found", None),
+            };
+        socket
+            .write_all(
+                format!(
+                    "HTTP/1.1 {status}\r\ncontent-type: text/plain; charset=utf-8\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{body}",
+                    body.len()
+                )
+                .as_bytes(),
+            )
+            .await?;
+
+        Ok(response)
+    }
+}
@@ -1,11 +1,14 @@
-use bigdecimal::BigDecimal;
+use crate::Error;
+use bigdecimal::{BigDecimal, Signed, Zero};
 use chrono::prelude::*;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use serde_with::DisplayFromStr;
+use serde_with::{DeserializeFromStr, DisplayFromStr, SerializeDisplay};
 use std::{
+    collections::{BTreeMap, HashMap, HashSet},
     fmt::{Debug, Display},
     str::FromStr,
+    time::Duration,
 };
 use strum::*;
 
@@ -19,12 +22,123 @@ pub enum AccessScope {
     PaymentP2P,
 }
 
+impl FromStr for AccessScope {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        serde_json::from_value(Value::String(s.to_string())).map_err(|_| Error::UnknownScope {
+            scope: s.to_string(),
+        })
+    }
+}
+
+/// Parse a granted `scope` string, as found in token responses, separated by spaces or commas.
+///
+/// Fails with [`Error::UnknownScope`] on the first scope this library does not know.
+#[allow(clippy::missing_errors_doc)]
+pub fn parse_scopes(s: &str) -> Result<HashSet<AccessScope>, Error> {
+    s.split(|c: char| c.is_whitespace() || c == ',')
+        .filter(|scope| !scope.is_empty())
+        .map(str::parse)
+        .collect()
+}
+
+#[derive(Clone, Debug)]
+pub struct AuthorizeOptions {
+    pub scopes: HashSet<AccessScope>,
+    /// Separator placed between scopes in the `scope` parameter.
+    pub scope_delimiter: String,
+    /// Fixed `instance_name`. A random one is generated if not set.
+    pub instance_name: Option<String>,
+    /// Additional parameters passed as is to `oauth/authorize`.
+    pub extra_params: HashMap<String, String>,
+    /// Anti-CSRF `state`. A random one is generated if not set.
+    pub state: Option<String>,
+}
+
+/// Parameters YooMoney appends to the redirect URI after the user grants access.
+#[derive(Clone, Debug)]
+pub struct AuthorizationResponse {
+    pub code: String,
+    /// Echoed `state`, which has to match the one sent in the request.
+    pub state: Option<String>,
+}
+
+impl AuthorizationResponse {
+    /// Extract `code` and `state` from the URL the browser was redirected to.
+    ///
+    /// Fails with [`Error::AuthorizationDenied`] if the redirect reports an error instead,
+    /// e.g. because the user refused access.
+    #[allow(clippy::missing_errors_doc)]
+    pub fn from_redirect_url(url: &str) -> anyhow::Result<Self> {
+        let url = reqwest::Url::parse(url.trim())?;
+        let param = |name: &str| {
+            url.query_pairs()
+                .find_map(|(k, v)| (k == name).then(|| v.into_owned()))
+        };
+
+        if let Some(error) = param("error") {
+            return Err(Error::AuthorizationDenied {
+                reason: match param("error_description") {
+                    Some(description) => format!("{error}: {description}"),
+                    None => error,
+                },
+            }
+            .into());
+        }
+
+        Ok(Self {
+            code: param("code").ok_or_else(|| {
+                anyhow::format_err!("Authorization code not found in redirect URL")
+            })?,
+            state: param("state"),
+        })
+    }
+}
+
+impl Default for AuthorizeOptions {
+    fn default() -> Self {
+        Self {
+            scopes: HashSet::new(),
+            scope_delimiter: " ".into(),
+            instance_name: None,
+            extra_params: HashMap::new(),
+            state: None,
+        }
+    }
+}
+
+impl AuthorizeOptions {
+    /// Value of the `scope` parameter, the inverse of [`parse_scopes`].
+    #[must_use]
+    pub fn scope_param(&self) -> String {
+        self.scopes
+            .iter()
+            .map(|s| match serde_json::to_value(s) {
+                Ok(Value::String(s)) => s,
+                _ => unreachable!("scopes serialize to plain strings; qed"),
+            })
+            .collect::<Vec<_>>()
+            .join(&self.scope_delimiter)
+    }
+}
+
+impl From<HashSet<AccessScope>> for AuthorizeOptions {
+    fn from(scopes: HashSet<AccessScope>) -> Self {
+        Self {
+            scopes,
+            ..Default::default()
+        }
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct TokenExchangeData {
     pub access_token: String,
 }
 
-#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+/// Ordered by level of identification, so that an upgrade compares greater.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum AccountStatus {
     Anonymous,
@@ -32,23 +146,131 @@ pub enum AccountStatus {
     Identified,
 }
 
-#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum AccountType {
     Personal,
     Professional,
 }
 
+/// (De)serialize decimals as JSON numbers, the way the API sends them, rather than strings.
+///
+/// Amounts are read through their shortest decimal form, which is exact for anything `f64` holds
+/// to the kopeck. With the `arbitrary-precision` feature, numbers are read and written as their
+/// original text instead, whatever their number of digits.
+/// Strings and integers are accepted as well.
+pub mod decimal_number {
+    use bigdecimal::BigDecimal;
+    use serde::{
+        de::{self, value::MapAccessDeserializer, MapAccess, Visitor},
+        ser::Error,
+        Deserialize, Deserializer, Serialize, Serializer,
+    };
+    use std::{fmt, str::FromStr};
+
+    struct DecimalVisitor;
+
+    impl<'de> Visitor<'de> for DecimalVisitor {
+        type Value = BigDecimal;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("a decimal number or string")
+        }
+
+        fn visit_str<E: de::Error>(self, v: &str) -> Result<BigDecimal, E> {
+            BigDecimal::from_str(v).map_err(E::custom)
+        }
+
+        fn visit_u64<E: de::Error>(self, v: u64) -> Result<BigDecimal, E> {
+            Ok(v.into())
+        }
+
+        fn visit_i64<E: de::Error>(self, v: i64) -> Result<BigDecimal, E> {
+            Ok(v.into())
+        }
+
+        fn visit_f64<E: de::Error>(self, v: f64) -> Result<BigDecimal, E> {
+            if !v.is_finite() {
+                return Err(E::custom("decimal out of range"));
+            }
+            // `Display` of `f64` is the shortest representation that parses back to the same value
+            self.visit_str(&v.to_string())
+        }
+
+        /// Arbitrary precision numbers, which serde_json hands over as a map that only it can read.
+        fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<BigDecimal, A::Error> {
+            let n = serde_json::Number::deserialize(MapAccessDeserializer::new(map))?;
+            self.visit_str(&n.to_string())
+        }
+    }
+
+    pub fn serialize<S: Serializer>(v: &BigDecimal, serializer: S) -> Result<S::Ok, S::Error> {
+        serde_json::Number::from_str(&v.to_string())
+            .map_err(S::Error::custom)?
+            .serialize(serializer)
+    }
+
+    pub fn serialize_option<S: Serializer>(
+        v: &Option<BigDecimal>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match v {
+            Some(v) => serialize(v, serializer),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<BigDecimal, D::Error> {
+        deserializer.deserialize_any(DecimalVisitor)
+    }
+
+    pub fn deserialize_option<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<BigDecimal>, D::Error> {
+        #[derive(Deserialize)]
+        struct Wrapper(#[serde(deserialize_with = "deserialize")] BigDecimal);
+
+        Ok(Option::<Wrapper>::deserialize(deserializer)?.map(|Wrapper(v)| v))
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct BalanceDetails {
+    #[serde(with = "decimal_number")]
     pub total: BigDecimal,
+    #[serde(with = "decimal_number")]
     pub available: BigDecimal,
+    #[serde(with = "decimal_number")]
     pub deposition_pending: BigDecimal,
+    #[serde(with = "decimal_number")]
     pub blocked: BigDecimal,
+    #[serde(with = "decimal_number")]
     pub debt: BigDecimal,
+    #[serde(with = "decimal_number")]
     pub hold: BigDecimal,
 }
 
+impl BalanceDetails {
+    /// Funds that are on the account but cannot be spent: blocked, on hold and owed.
+    #[must_use]
+    pub fn unavailable(&self) -> BigDecimal {
+        &self.blocked + &self.hold + &self.debt
+    }
+
+    /// Incoming funds that have not been credited yet.
+    #[must_use]
+    pub fn pending_in(&self) -> BigDecimal {
+        self.deposition_pending.clone()
+    }
+
+    /// Whether available and unavailable funds add up to the total, to the kopeck.
+    #[must_use]
+    pub fn reconciles(&self) -> bool {
+        let diff = (&self.available + self.unavailable() - &self.total).abs();
+        diff < BigDecimal::new(1.into(), 2)
+    }
+}
+
 #[derive(Clone, Copy, Debug, Serialize, Deserialize)]
 pub enum CardType {
     VISA,
@@ -59,22 +281,122 @@ pub enum CardType {
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct LinkedCard {
-    #[serde(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub pan_fragment: Option<String>,
-    #[serde(default, rename = "type")]
+    #[serde(default, rename = "type", skip_serializing_if = "Option::is_none")]
     pub card_type: Option<CardType>,
 }
 
+fn default_currency() -> String {
+    "643".into()
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct AccountInfo {
     pub account: String,
+    /// May be omitted for freshly created anonymous wallets, in which case it is zero.
+    #[serde(default, with = "decimal_number")]
     pub balance: BigDecimal,
+    /// ISO 4217 numeric code. Assumed to be RUB if omitted.
+    #[serde(default = "default_currency")]
     pub currency: String,
     pub account_status: AccountStatus,
     pub account_type: AccountType,
-    #[serde(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub balance_details: Option<BalanceDetails>,
     pub cards_linked: Vec<LinkedCard>,
+    /// Loyalty balance kept apart from the main one. Only reported for accounts that have it.
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "decimal_number::serialize_option",
+        deserialize_with = "decimal_number::deserialize_option"
+    )]
+    pub bonus_balance: Option<BigDecimal>,
+}
+
+impl AccountInfo {
+    /// Bonus balance, zero for accounts without one.
+    #[must_use]
+    pub fn bonus(&self) -> BigDecimal {
+        self.bonus_balance.clone().unwrap_or_default()
+    }
+
+    /// Changes from this snapshot to a later one.
+    ///
+    /// Cards are matched by masked number; cards without one are not compared.
+    #[must_use]
+    pub fn diff(&self, other: &AccountInfo) -> AccountDiff {
+        let pans = |info: &AccountInfo| {
+            info.cards_linked
+                .iter()
+                .filter_map(|card| card.pan_fragment.clone())
+                .collect::<HashSet<_>>()
+        };
+        let (before, after) = (pans(self), pans(other));
+        let cards_added = other
+            .cards_linked
+            .iter()
+            .filter(|card| {
+                card.pan_fragment
+                    .as_ref()
+                    .is_some_and(|pan| !before.contains(pan))
+            })
+            .cloned()
+            .collect();
+        let cards_removed = self
+            .cards_linked
+            .iter()
+            .filter(|card| {
+                card.pan_fragment
+                    .as_ref()
+                    .is_some_and(|pan| !after.contains(pan))
+            })
+            .cloned()
+            .collect();
+
+        AccountDiff {
+            balance_change: &other.balance - &self.balance,
+            cards_added,
+            cards_removed,
+            status_change: (self.account_status != other.account_status)
+                .then_some((self.account_status, other.account_status)),
+            type_change: (self.account_type != other.account_type)
+                .then_some((self.account_type, other.account_type)),
+        }
+    }
+}
+
+/// Difference between two [`AccountInfo`] snapshots, see [`AccountInfo::diff`].
+#[derive(Clone, Debug)]
+pub struct AccountDiff {
+    /// Later balance minus earlier one.
+    pub balance_change: BigDecimal,
+    pub cards_added: Vec<LinkedCard>,
+    pub cards_removed: Vec<LinkedCard>,
+    /// Earlier and later status, if different.
+    pub status_change: Option<(AccountStatus, AccountStatus)>,
+    /// Earlier and later type, if different.
+    pub type_change: Option<(AccountType, AccountType)>,
+}
+
+impl AccountDiff {
+    /// Whether nothing tracked has changed.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.balance_change.is_zero()
+            && self.cards_added.is_empty()
+            && self.cards_removed.is_empty()
+            && self.status_change.is_none()
+            && self.type_change.is_none()
+    }
+
+    /// Whether the account moved to a higher identification level.
+    #[must_use]
+    pub fn is_status_upgrade(&self) -> bool {
+        self.status_change
+            .is_some_and(|(before, after)| after > before)
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -84,27 +406,98 @@ where
     T::Err: Display;
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct OperationHistoryResponse {
+pub struct OperationHistoryResponse<T = Operation> {
     pub next_record: Option<StringNumber<u64>>,
-    pub operations: Vec<Operation>,
+    pub operations: Vec<T>,
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+/// Which operations a single history request asks for, see [`Client::operation_history_page`](crate::Client::operation_history_page).
+#[derive(Clone, Debug, Default)]
+pub struct HistoryFilter {
+    /// All types if empty.
+    pub operation_types: HashSet<ReqOperationType>,
+    pub label: Option<String>,
+    pub range: DateRange,
+    pub details: bool,
+}
+
+/// Bounds for `from`/`till` of operation history. Unset bound means unbounded.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DateRange {
+    pub from: Option<DateTime<Utc>>,
+    pub till: Option<DateTime<Utc>>,
+}
+
+impl DateRange {
+    #[must_use]
+    pub fn between(from: DateTime<Utc>, till: DateTime<Utc>) -> Self {
+        Self {
+            from: Some(from),
+            till: Some(till),
+        }
+    }
+
+    /// Last `days` days up to now.
+    #[must_use]
+    pub fn last_days(days: u32) -> Self {
+        Self::last_days_before(days, Utc::now())
+    }
+
+    #[must_use]
+    pub fn last_days_before(days: u32, now: DateTime<Utc>) -> Self {
+        Self::between(now - chrono::Duration::days(days.into()), now)
+    }
+
+    /// From the start of the current calendar month (in UTC) up to now.
+    #[must_use]
+    pub fn this_month() -> Self {
+        Self::month_before(Utc::now())
+    }
+
+    #[must_use]
+    pub fn month_before(now: DateTime<Utc>) -> Self {
+        let start = Utc
+            .with_ymd_and_hms(now.year(), now.month(), 1, 0, 0, 0)
+            .single()
+            .expect("first day of month always exists; qed");
+        Self::between(start, now)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize, Display)]
 #[serde(rename_all = "kebab-case")]
+#[strum(serialize_all = "kebab-case")]
 pub enum ReqOperationType {
     Deposition,
     Payment,
     IncomingTransfersUnaccepted,
 }
 
-#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, EnumString)]
 #[serde(rename_all = "kebab-case")]
+#[strum(serialize_all = "kebab-case")]
 pub enum RspOperationType {
     PaymentShop,
     OutgoingTransfer,
     Deposition,
     IncomingTransfer,
     IncomingTransferProtected,
+    /// Type missing, as for some legacy operations, or not known to this library.
+    #[default]
+    Unknown,
+}
+
+impl<'de> Deserialize<'de> for RspOperationType {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        Ok(s.parse().unwrap_or_else(|_| {
+            crate::note_fallback(|| format!("unknown operation type {s:?}"));
+            Self::Unknown
+        }))
+    }
 }
 
 #[derive(Clone, Copy, Debug, Serialize, Deserialize)]
@@ -123,6 +516,7 @@ pub enum TransferDirection {
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(from = "RawOperation")]
 pub struct Operation {
     pub operation_id: String,
     pub status: OperationStatus,
@@ -130,15 +524,110 @@ pub struct Operation {
     pub title: String,
     pub pattern_id: Option<String>,
     pub direction: TransferDirection,
+    /// Absolute value of the operation amount, see [`signed_amount`](Self::signed_amount).
+    #[serde(serialize_with = "decimal_number::serialize")]
     pub amount: BigDecimal,
     #[serde(default)]
     pub label: Option<String>,
+    #[serde(default)]
+    pub group_id: Option<String>,
     #[serde(rename = "type")]
     pub operation_type: RspOperationType,
+    /// Whether the transfer is protected by a code, if history reports it.
+    #[serde(default)]
+    pub codepro: Option<bool>,
+    /// Hint for the protection code left by the sender, if any.
+    #[serde(default)]
+    pub protection_code_hint: Option<String>,
 }
 
-#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+impl Operation {
+    /// Protected transfer, judging by [`codepro`](Self::codepro) or the operation type,
+    /// without a separate [`operation_details`](crate::API::operation_details) call.
+    #[must_use]
+    pub fn is_protected(&self) -> bool {
+        self.codepro
+            .unwrap_or(self.operation_type == RspOperationType::IncomingTransferProtected)
+    }
+
+    /// Amount that is negative for outgoing operations.
+    #[must_use]
+    pub fn signed_amount(&self) -> BigDecimal {
+        match self.direction {
+            TransferDirection::In => self.amount.clone(),
+            TransferDirection::Out => -self.amount.clone(),
+        }
+    }
+}
+
+/// Operation as sent over the wire. Direction may be missing and amount may carry the sign instead.
+#[derive(Deserialize)]
+struct RawOperation {
+    operation_id: String,
+    status: OperationStatus,
+    datetime: DateTime<Utc>,
+    title: String,
+    pattern_id: Option<String>,
+    #[serde(default)]
+    direction: Option<TransferDirection>,
+    #[serde(deserialize_with = "decimal_number::deserialize")]
+    amount: BigDecimal,
+    #[serde(default)]
+    label: Option<String>,
+    #[serde(default)]
+    group_id: Option<String>,
+    #[serde(default, rename = "type")]
+    operation_type: RspOperationType,
+    #[serde(default)]
+    codepro: Option<bool>,
+    #[serde(default)]
+    protection_code_hint: Option<String>,
+}
+
+impl From<RawOperation> for Operation {
+    fn from(raw: RawOperation) -> Self {
+        let negative = raw.amount.sign() == bigdecimal::num_bigint::Sign::Minus;
+        let direction = raw.direction.unwrap_or(if negative {
+            TransferDirection::Out
+        } else {
+            TransferDirection::In
+        });
+
+        Self {
+            operation_id: raw.operation_id,
+            status: raw.status,
+            datetime: raw.datetime,
+            title: raw.title,
+            pattern_id: raw.pattern_id,
+            direction,
+            amount: raw.amount.abs(),
+            label: raw.label,
+            group_id: raw.group_id,
+            operation_type: raw.operation_type,
+            codepro: raw.codepro,
+            protection_code_hint: raw.protection_code_hint,
+        }
+    }
+}
+
+/// Group operations belonging to the same recurring payment series by their `group_id`.
+/// Operations without a group are skipped.
+pub fn group_operations(
+    ops: impl IntoIterator<Item = Operation>,
+) -> HashMap<String, Vec<Operation>> {
+    let mut groups = HashMap::<_, Vec<_>>::new();
+    for op in ops {
+        if let Some(group_id) = op.group_id.clone() {
+            groups.entry(group_id).or_default().push(op);
+        }
+    }
+
+    groups
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, Display)]
 #[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
 pub enum RecipientType {
     Account,
     Phone,
@@ -152,6 +641,54 @@ pub enum UserId {
     Email(String),
 }
 
+impl UserId {
+    pub const ACCOUNT_PREFIX: &'static str = "4100";
+    pub const ACCOUNT_DIGITS: std::ops::RangeInclusive<usize> = 11..=20;
+
+    /// Wallet account number with validation: the whole number, prefix included,
+    /// must have 11 to 20 digits and start with `4100`.
+    ///
+    /// Use [`UserId::Account`] directly to bypass validation.
+    #[allow(clippy::missing_errors_doc)]
+    pub fn account(n: u64) -> anyhow::Result<Self> {
+        let s = n.to_string();
+        if !Self::ACCOUNT_DIGITS.contains(&s.len()) {
+            anyhow::bail!(
+                "account number must have {} to {} digits",
+                Self::ACCOUNT_DIGITS.start(),
+                Self::ACCOUNT_DIGITS.end()
+            );
+        }
+        if !s.starts_with(Self::ACCOUNT_PREFIX) {
+            anyhow::bail!("account number must start with {}", Self::ACCOUNT_PREFIX);
+        }
+
+        Ok(Self::Account(n))
+    }
+
+    /// Parse account number as typed by a human, ignoring spaces and dashes.
+    ///
+    /// The leading `4100` is not stripped: it is part of the wallet number the API
+    /// expects, so the result is validated by the same rules as [`UserId::account`].
+    #[allow(clippy::missing_errors_doc)]
+    pub fn parse_account(s: &str) -> anyhow::Result<Self> {
+        let digits = s
+            .chars()
+            .filter(|c| !c.is_whitespace() && *c != '-')
+            .collect::<String>();
+        Self::account(digits.parse()?)
+    }
+
+    #[must_use]
+    pub fn recipient_type(&self) -> RecipientType {
+        match self {
+            Self::Account(_) => RecipientType::Account,
+            Self::Phone(_) => RecipientType::Phone,
+            Self::Email(_) => RecipientType::Email,
+        }
+    }
+}
+
 impl Display for UserId {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -162,12 +699,256 @@ impl Display for UserId {
     }
 }
 
+/// Shop payment parameter that may hold arrays or nested fields, for patterns that expect e.g. `items[0][name]`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ShopParam {
+    Value(String),
+    /// Encoded as `key[0]`, `key[1]`, ...
+    List(Vec<ShopParam>),
+    /// Encoded as `key[field]`.
+    Map(BTreeMap<String, ShopParam>),
+}
+
+impl From<String> for ShopParam {
+    fn from(value: String) -> Self {
+        Self::Value(value)
+    }
+}
+
+impl From<&str> for ShopParam {
+    fn from(value: &str) -> Self {
+        Self::Value(value.to_string())
+    }
+}
+
+impl ShopParam {
+    fn flatten_into(self, key: String, out: &mut HashMap<String, String>) {
+        match self {
+            Self::Value(v) => {
+                out.insert(key, v);
+            }
+            Self::List(items) => {
+                for (i, item) in items.into_iter().enumerate() {
+                    item.flatten_into(format!("{key}[{i}]"), out);
+                }
+            }
+            Self::Map(fields) => {
+                for (field, value) in fields {
+                    value.flatten_into(format!("{key}[{field}]"), out);
+                }
+            }
+        }
+    }
+}
+
+/// Encode structured params into flat form fields with bracketed keys.
+#[must_use]
+pub fn flatten_shop_params(params: BTreeMap<String, ShopParam>) -> HashMap<String, String> {
+    let mut out = HashMap::new();
+    for (key, value) in params {
+        value.flatten_into(key, &mut out);
+    }
+    out
+}
+
 #[derive(Clone, Debug)]
 pub enum RequestAmount {
     Total(BigDecimal),
     Net(BigDecimal),
 }
 
+/// Code the recipient has to enter to accept a protected transfer: exactly 4 digits.
+#[derive(Clone, Debug, PartialEq, Eq, SerializeDisplay, DeserializeFromStr)]
+pub struct ProtectionCode(String);
+
+impl ProtectionCode {
+    pub const LEN: usize = 4;
+}
+
+impl FromStr for ProtectionCode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.len() != Self::LEN || !s.chars().all(|c| c.is_ascii_digit()) {
+            anyhow::bail!("protection code must be {} digits", Self::LEN);
+        }
+
+        Ok(Self(s.to_string()))
+    }
+}
+
+impl Display for ProtectionCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum IncomingTransferAcceptResponse {
+    Success,
+    Refused {
+        error: String,
+        /// Attempts left to enter the protection code, reported on `illegal_param_protection_code`.
+        #[serde(default)]
+        protection_code_attempts_available: Option<u32>,
+        /// Page where the user has to complete an action before accepting, on `ext_action_required`.
+        #[serde(default)]
+        ext_action_uri: Option<String>,
+    },
+}
+
+impl IncomingTransferAcceptResponse {
+    /// Protection code attempts left, if the server reported them.
+    #[must_use]
+    pub fn attempts_available(&self) -> Option<u32> {
+        match self {
+            Self::Refused {
+                protection_code_attempts_available,
+                ..
+            } => *protection_code_attempts_available,
+            Self::Success => None,
+        }
+    }
+}
+
+/// Protection code attempts left for one protected transfer, see [`Client::accept_protected_transfer`](crate::Client::accept_protected_transfer).
+///
+/// Once the sender's limit is used up, the transfer is locked for good, so after the server reports
+/// no attempts left, further attempts fail locally with [`Error::AttemptsExhausted`] without calling the API.
+#[derive(Clone, Debug)]
+pub struct ProtectionCodeAttempts {
+    pub operation_id: String,
+    /// Unknown until the server reports it on a refusal.
+    pub available: Option<u32>,
+}
+
+impl ProtectionCodeAttempts {
+    #[must_use]
+    pub fn new(operation_id: String) -> Self {
+        Self {
+            operation_id,
+            available: None,
+        }
+    }
+
+    #[must_use]
+    pub fn is_exhausted(&self) -> bool {
+        self.available == Some(0)
+    }
+
+    /// Take the attempts reported in `rsp` into account.
+    pub fn update(&mut self, rsp: &IncomingTransferAcceptResponse) {
+        if let Some(available) = rsp.attempts_available() {
+            self.available = Some(available);
+        }
+    }
+}
+
+/// Format an amount for display with exactly two decimal places, rounding half up.
+#[must_use]
+pub fn format_amount(amount: &BigDecimal) -> String {
+    amount
+        .with_scale_round(2, bigdecimal::RoundingMode::HalfUp)
+        .to_string()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FeeSource {
+    Wallet,
+    Card,
+}
+
+/// P2P transfer fee rates as fractions of the transferred amount.
+///
+/// Fees computed from it are estimates: rates change over time and the server has the final say,
+/// so always check `fee`/`amount_due` in the actual response.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FeeSchedule {
+    pub wallet: BigDecimal,
+    pub card: BigDecimal,
+}
+
+impl Default for FeeSchedule {
+    fn default() -> Self {
+        Self {
+            wallet: BigDecimal::new(5.into(), 3),
+            card: BigDecimal::new(2.into(), 2),
+        }
+    }
+}
+
+impl FeeSchedule {
+    #[must_use]
+    pub fn rate(&self, source: FeeSource) -> &BigDecimal {
+        match source {
+            FeeSource::Wallet => &self.wallet,
+            FeeSource::Card => &self.card,
+        }
+    }
+
+    /// Fee charged on top of `amount` received by the recipient, rounded up to kopecks.
+    #[must_use]
+    pub fn compute_fee(&self, amount: &BigDecimal, source: FeeSource) -> BigDecimal {
+        (amount * self.rate(source)).with_scale_round(2, bigdecimal::RoundingMode::Up)
+    }
+
+    /// Total charged to the sender for the recipient to get `amount`.
+    #[must_use]
+    pub fn total_for_net(&self, amount: &BigDecimal, source: FeeSource) -> BigDecimal {
+        amount + self.compute_fee(amount, source)
+    }
+
+    /// Largest amount the recipient gets when the sender is charged at most `total`.
+    #[must_use]
+    pub fn net_for_total(&self, total: &BigDecimal, source: FeeSource) -> BigDecimal {
+        let kopeck = BigDecimal::new(1.into(), 2);
+        let mut net = (total / (BigDecimal::from(1) + self.rate(source)))
+            .with_scale_round(2, bigdecimal::RoundingMode::Down);
+        // Fee is rounded up, which may take the total a kopeck over
+        while net > BigDecimal::zero() && self.total_for_net(&net, source) > *total {
+            net -= &kopeck;
+        }
+        net
+    }
+}
+
+/// Maximum length of a transfer comment, shown in the sender's history.
+pub const MAX_COMMENT_LEN: usize = 255;
+/// Maximum length of a transfer message, shown to the recipient.
+pub const MAX_MESSAGE_LEN: usize = 255;
+
+/// Check that `value` is at most `max` characters long, or cut it to that length if `truncate` is set.
+#[allow(clippy::missing_errors_doc)]
+pub fn limit_length(
+    field: &'static str,
+    value: String,
+    max: usize,
+    truncate: bool,
+) -> Result<String, Error> {
+    if value.chars().count() <= max {
+        Ok(value)
+    } else if truncate {
+        Ok(value.chars().take(max).collect())
+    } else {
+        Err(Error::FieldTooLong { field, max })
+    }
+}
+
+/// Parameters of a single p2p transfer, mirroring [`API::request_transfer`](crate::API::request_transfer).
+#[derive(Clone, Debug)]
+pub struct TransferSpec {
+    pub to: UserId,
+    pub amount: RequestAmount,
+    pub comment: String,
+    pub message: String,
+    pub label: Option<String>,
+    pub codepro: bool,
+    pub protection_code: Option<ProtectionCode>,
+    pub hold_for_pickup: bool,
+    pub expire_period: u32,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct OperationDetails {
     pub operation_id: String,
@@ -175,10 +956,19 @@ pub struct OperationDetails {
     #[serde(default)]
     pub pattern_id: Option<String>,
     pub direction: TransferDirection,
+    #[serde(with = "decimal_number")]
     pub amount: BigDecimal,
-    #[serde(default)]
+    #[serde(
+        default,
+        serialize_with = "decimal_number::serialize_option",
+        deserialize_with = "decimal_number::deserialize_option"
+    )]
     pub amount_due: Option<BigDecimal>,
-    #[serde(default)]
+    #[serde(
+        default,
+        serialize_with = "decimal_number::serialize_option",
+        deserialize_with = "decimal_number::deserialize_option"
+    )]
     pub fee: Option<BigDecimal>,
     pub datetime: DateTime<Utc>,
     pub title: String,
@@ -204,11 +994,194 @@ pub struct OperationDetails {
     pub label: Option<String>,
     #[serde(default)]
     pub details: Option<String>,
+    #[serde(default)]
+    pub group_id: Option<String>,
+    #[serde(default)]
     pub operation_type: RspOperationType,
     #[serde(default)]
     pub digital_goods: Option<String>,
 }
 
+/// Language for descriptions built on the client side, see [`OperationDetails::describe`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Locale {
+    #[default]
+    En,
+    Ru,
+}
+
+impl OperationDetails {
+    /// Fee charged, zero if none was reported.
+    #[must_use]
+    pub fn fee_or_zero(&self) -> BigDecimal {
+        self.fee.clone().unwrap_or_else(BigDecimal::zero)
+    }
+
+    /// Amount debited from the sender, which equals [`amount`](Self::amount) when no separate due amount was reported.
+    #[must_use]
+    pub fn amount_due_or_amount(&self) -> BigDecimal {
+        self.amount_due
+            .clone()
+            .unwrap_or_else(|| self.amount.clone())
+    }
+
+    /// Describe the operation from its typed fields, for apps that want their own wording instead of
+    /// the server-rendered [`title`](Self::title). Falls back to `title` if the fields needed are missing.
+    #[must_use]
+    pub fn describe(&self, locale: Locale) -> String {
+        let amount = format_amount(&self.amount);
+        let described = match (self.operation_type, locale) {
+            (RspOperationType::Deposition, Locale::En) => Some(format!("Deposit of {amount} RUB")),
+            (RspOperationType::Deposition, Locale::Ru) => Some(format!("Пополнение на {amount} ₽")),
+            (RspOperationType::OutgoingTransfer, Locale::En) => self
+                .recipient
+                .as_ref()
+                .map(|to| format!("Transfer of {amount} RUB to {to}")),
+            (RspOperationType::OutgoingTransfer, Locale::Ru) => self
+                .recipient
+                .as_ref()
+                .map(|to| format!("Перевод {amount} ₽ получателю {to}")),
+            (RspOperationType::IncomingTransfer, Locale::En) => self
+                .sender
+                .as_ref()
+                .map(|from| format!("Transfer of {amount} RUB from {from}")),
+            (RspOperationType::IncomingTransfer, Locale::Ru) => self
+                .sender
+                .as_ref()
+                .map(|from| format!("Перевод {amount} ₽ от {from}")),
+            (RspOperationType::IncomingTransferProtected, Locale::En) => self
+                .sender
+                .as_ref()
+                .map(|from| format!("Transfer of {amount} RUB from {from}, protected by code")),
+            (RspOperationType::IncomingTransferProtected, Locale::Ru) => self
+                .sender
+                .as_ref()
+                .map(|from| format!("Перевод {amount} ₽ от {from} с кодом протекции")),
+            // Shop payments carry no typed description of the shop
+            (RspOperationType::PaymentShop | RspOperationType::Unknown, _) => None,
+        };
+
+        described.unwrap_or_else(|| self.title.clone())
+    }
+
+    /// Fields specific to the operation type, see [`OperationKind`].
+    #[allow(clippy::missing_errors_doc)]
+    pub fn kind(&self) -> Result<OperationKind, Error> {
+        Ok(match self.operation_type {
+            RspOperationType::PaymentShop => OperationKind::ShopPayment(ShopPayment {
+                pattern_id: self.pattern_id.clone(),
+                amount_due: self.amount_due_or_amount(),
+                fee: self.fee_or_zero(),
+                details: self.details.clone(),
+                digital_goods: self.digital_goods.clone(),
+            }),
+            RspOperationType::OutgoingTransfer => {
+                OperationKind::OutgoingTransfer(OutgoingTransfer {
+                    recipient: self.recipient.clone().ok_or_else(|| {
+                        Error::IncompleteOperation {
+                            operation_id: self.operation_id.clone(),
+                            field: "recipient",
+                        }
+                    })?,
+                    recipient_type: self.recipient_type,
+                    amount_due: self.amount_due_or_amount(),
+                    fee: self.fee_or_zero(),
+                    message: self.message.clone(),
+                    comment: self.comment.clone(),
+                    protection_code: self.protection_code.clone(),
+                    expires: self.expires,
+                })
+            }
+            RspOperationType::Deposition => OperationKind::Deposition(Deposition {
+                details: self.details.clone(),
+            }),
+            RspOperationType::IncomingTransfer => {
+                OperationKind::IncomingTransfer(IncomingTransfer {
+                    sender: self.sender.clone(),
+                    message: self.message.clone(),
+                    comment: self.comment.clone(),
+                })
+            }
+            RspOperationType::IncomingTransferProtected => {
+                OperationKind::IncomingTransferProtected(IncomingTransferProtected {
+                    sender: self.sender.clone(),
+                    message: self.message.clone(),
+                    comment: self.comment.clone(),
+                    expires: self.expires,
+                    answer_datetime: self.answer_datetime,
+                })
+            }
+            RspOperationType::Unknown => {
+                return Err(Error::IncompleteOperation {
+                    operation_id: self.operation_id.clone(),
+                    field: "operation_type",
+                })
+            }
+        })
+    }
+}
+
+/// Typed view of [`OperationDetails`] carrying only the fields relevant to its operation type.
+/// Fields common to all types stay on `OperationDetails`.
+#[derive(Clone, Debug)]
+pub enum OperationKind {
+    ShopPayment(ShopPayment),
+    OutgoingTransfer(OutgoingTransfer),
+    Deposition(Deposition),
+    IncomingTransfer(IncomingTransfer),
+    IncomingTransferProtected(IncomingTransferProtected),
+}
+
+#[derive(Clone, Debug)]
+pub struct ShopPayment {
+    pub pattern_id: Option<String>,
+    /// Amount debited, including the fee.
+    pub amount_due: BigDecimal,
+    pub fee: BigDecimal,
+    pub details: Option<String>,
+    pub digital_goods: Option<String>,
+}
+
+#[derive(Clone, Debug)]
+pub struct OutgoingTransfer {
+    pub recipient: String,
+    pub recipient_type: Option<RecipientType>,
+    /// Amount debited, including the fee.
+    pub amount_due: BigDecimal,
+    pub fee: BigDecimal,
+    pub message: Option<String>,
+    pub comment: Option<String>,
+    /// Code to pass on to the recipient, if the transfer is protected.
+    pub protection_code: Option<String>,
+    /// When an unaccepted protected transfer is returned.
+    pub expires: Option<DateTime<Utc>>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Deposition {
+    pub details: Option<String>,
+}
+
+#[derive(Clone, Debug)]
+pub struct IncomingTransfer {
+    /// Missing for anonymous transfers.
+    pub sender: Option<String>,
+    pub message: Option<String>,
+    pub comment: Option<String>,
+}
+
+#[derive(Clone, Debug)]
+pub struct IncomingTransferProtected {
+    /// Missing for anonymous transfers.
+    pub sender: Option<String>,
+    pub message: Option<String>,
+    pub comment: Option<String>,
+    /// Deadline for accepting the transfer, after which it is returned to the sender.
+    pub expires: Option<DateTime<Utc>>,
+    /// When the transfer was accepted or rejected.
+    pub answer_datetime: Option<DateTime<Utc>>,
+}
+
 #[derive(Clone, Debug)]
 pub enum TestCard {
     Available,
@@ -221,6 +1194,35 @@ pub enum TestResult {
     Other(String),
 }
 
+impl TestCard {
+    #[must_use]
+    pub fn as_param(&self) -> &str {
+        match self {
+            Self::Available => "available",
+            Self::Custom(card) => card,
+        }
+    }
+}
+
+impl TestResult {
+    #[must_use]
+    pub fn as_param(&self) -> &str {
+        match self {
+            Self::Success => "success",
+            Self::Other(result) => result,
+        }
+    }
+}
+
+/// Test mode of `process-payment`: no money is moved, and the outcome may be forced with `result`.
+#[derive(Clone, Debug, Default)]
+pub struct TestPayment {
+    /// Pretend to pay with this card.
+    pub card: Option<TestCard>,
+    /// Error code to respond with, or success.
+    pub result: Option<TestResult>,
+}
+
 #[derive(Clone, Debug, Deserialize)]
 pub struct WalletSource {
     pub allowed: bool,
@@ -246,11 +1248,83 @@ pub struct MoneySources {
     pub cards: CardsSource,
 }
 
+/// Funding options known from account info alone, without initiating a payment.
+///
+/// Linked cards have no id until `request-payment` offers them, see [`LinkedCard::find_in`].
+#[derive(Clone, Debug)]
+pub struct LinkedMoneySources {
+    pub wallet: WalletSource,
+    pub cards: Vec<LinkedCard>,
+}
+
+impl From<&AccountInfo> for LinkedMoneySources {
+    fn from(info: &AccountInfo) -> Self {
+        let available = info
+            .balance_details
+            .as_ref()
+            .map_or(&info.balance, |details| &details.available);
+
+        Self {
+            wallet: WalletSource {
+                allowed: available.is_positive(),
+            },
+            cards: info.cards_linked.clone(),
+        }
+    }
+}
+
+impl LinkedCard {
+    /// Card among money sources offered by `request-payment` with the same masked number.
+    #[must_use]
+    pub fn find_in<'a>(&self, sources: &'a MoneySources) -> Option<&'a CardSource> {
+        let pan = self.pan_fragment.as_deref()?;
+        sources
+            .cards
+            .items
+            .as_deref()
+            .unwrap_or_default()
+            .iter()
+            .find(|card| card.data.pan_fragment.as_deref() == Some(pan))
+    }
+}
+
 #[derive(Clone, Debug, Deserialize)]
 pub struct RequestPaymentSuccessData {
+    #[serde(deserialize_with = "decimal_number::deserialize")]
     pub balance: BigDecimal,
     pub request_id: String,
     pub money_source: MoneySources,
+    #[serde(default)]
+    pub recipient_account_status: Option<AccountStatus>,
+    #[serde(default)]
+    pub recipient_account_type: Option<AccountType>,
+    /// Amount to be debited from the payer, fee included.
+    #[serde(default, deserialize_with = "decimal_number::deserialize_option")]
+    pub contract_amount: Option<BigDecimal>,
+    /// Deadline for processing the payment with `request_id`, if the response reports one.
+    #[serde(default)]
+    pub request_valid_until: Option<DateTime<Utc>>,
+}
+
+impl RequestPaymentSuccessData {
+    /// Whether `request_id` is past its reported validity window. Unknown validity counts as not expired.
+    #[must_use]
+    pub fn is_request_expired(&self) -> bool {
+        self.is_request_expired_at(Utc::now())
+    }
+
+    #[must_use]
+    pub fn is_request_expired_at(&self, now: DateTime<Utc>) -> bool {
+        self.request_valid_until.is_some_and(|t| t <= now)
+    }
+}
+
+/// What YooMoney reports about a p2p transfer recipient.
+#[derive(Clone, Debug)]
+pub struct RecipientInfo {
+    pub recipient_type: RecipientType,
+    pub account_status: Option<AccountStatus>,
+    pub account_type: Option<AccountType>,
 }
 
 #[derive(Clone, Debug, Deserialize)]
@@ -258,16 +1332,57 @@ pub struct RequestPaymentSuccessData {
 pub enum RequestPaymentResponse {
     Success(RequestPaymentSuccessData),
     HoldForPickup(RequestPaymentSuccessData),
-    Refused { error: String },
+    Refused {
+        error: String,
+        /// Page where the user can unblock the account, with `error` being `account_blocked`.
+        #[serde(default)]
+        account_unblock_uri: Option<String>,
+        /// Page where the user has to complete an action first, with `error` being `ext_action_required`.
+        #[serde(default)]
+        ext_action_uri: Option<String>,
+    },
+    /// Payer has to confirm something at `ext_action_uri` before the payment can be requested again.
+    ExtActionRequired {
+        #[serde(default)]
+        ext_action_uri: Option<String>,
+    },
 }
 
 impl RequestPaymentResponse {
+    /// Request id and hold-for-pickup flag, or the error code, which is `ext_action_required`
+    /// for [`ExtActionRequired`](Self::ExtActionRequired).
     #[allow(clippy::missing_errors_doc)]
     pub fn into_result(self) -> Result<(bool, RequestPaymentSuccessData), String> {
         match self {
             Self::Success(data) => Ok((false, data)),
             Self::HoldForPickup(data) => Ok((true, data)),
-            Self::Refused { error } => Err(error),
+            Self::Refused { error, .. } => Err(error),
+            Self::ExtActionRequired { .. } => Err("ext_action_required".into()),
+        }
+    }
+
+    /// Page the user has to visit before the payment can go through, if that is why it was refused.
+    #[must_use]
+    pub fn action_uri(&self) -> Option<&str> {
+        match self {
+            Self::Refused {
+                account_unblock_uri,
+                ext_action_uri,
+                ..
+            } => account_unblock_uri.as_deref().or(ext_action_uri.as_deref()),
+            Self::ExtActionRequired { ext_action_uri } => ext_action_uri.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// Id to pass to `process_payment` and whether the transfer will be held for pickup,
+    /// unless the request was refused.
+    #[must_use]
+    pub fn request_id(&self) -> Option<(&str, bool)> {
+        match self {
+            Self::Success(data) => Some((&data.request_id, false)),
+            Self::HoldForPickup(data) => Some((&data.request_id, true)),
+            Self::Refused { .. } | Self::ExtActionRequired { .. } => None,
         }
     }
 }
@@ -285,18 +1400,73 @@ pub enum ProcessPaymentMoneySource {
     Card {
         id: String,
         secure3d: Option<Secure3DData>,
+        /// Card security code, required when `CardsSource::csc_required` is set.
+        csc: Option<String>,
     },
 }
 
+impl ProcessPaymentMoneySource {
+    /// Check fields that can be verified without calling the API.
+    #[allow(clippy::missing_errors_doc)]
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if let Self::Card { csc: Some(csc), .. } = self {
+            if !(3..=4).contains(&csc.len()) || !csc.chars().all(|c| c.is_ascii_digit()) {
+                anyhow::bail!("CSC must be 3 or 4 digits");
+            }
+        }
+
+        Ok(())
+    }
+
+    #[must_use]
+    pub fn from_card_source(card: &CardSource) -> Self {
+        Self::Card {
+            id: card.id.clone(),
+            secure3d: None,
+            csc: None,
+        }
+    }
+
+    /// Find a card among money sources offered by `request-payment` by its masked number,
+    /// either in full (`510000******9999`) or by trailing digits (`9999`).
+    #[allow(clippy::missing_errors_doc)]
+    pub fn card_by_fragment(sources: &MoneySources, fragment: &str) -> anyhow::Result<Self> {
+        let cards = sources.cards.items.as_deref().unwrap_or_default();
+        let mut matching = cards.iter().filter(|card| {
+            card.data
+                .pan_fragment
+                .as_deref()
+                .is_some_and(|pan| pan == fragment || pan.ends_with(fragment))
+        });
+
+        match (matching.next(), matching.next()) {
+            (Some(card), None) => Ok(Self::from_card_source(card)),
+            (None, _) => anyhow::bail!("no linked card matches {fragment}"),
+            (Some(_), Some(_)) => anyhow::bail!("more than one linked card matches {fragment}"),
+        }
+    }
+}
+
+impl From<&CardSource> for ProcessPaymentMoneySource {
+    fn from(card: &CardSource) -> Self {
+        Self::from_card_source(card)
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct ProcessPaymentSuccessData {
     pub payment_id: String,
+    #[serde(with = "decimal_number")]
     pub balance: BigDecimal,
     pub invoice_id: String,
     pub payer: String,
     pub payee: String,
+    #[serde(with = "decimal_number")]
     pub credit_amount: BigDecimal,
     pub hold_for_pickup_link: String,
+    /// Protection code in effect for a protected transfer.
+    #[serde(default)]
+    pub protection_code: Option<String>,
     #[serde(default)]
     pub acs_uri: Option<String>,
     #[serde(default)]
@@ -304,6 +1474,57 @@ pub struct ProcessPaymentSuccessData {
     pub digital_goods: Value,
 }
 
+/// 3-D Secure challenge: `params` are to be POSTed to `uri` in the user's browser.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AcsChallenge {
+    pub uri: String,
+    pub params: Value,
+}
+
+/// What the user has to do outside of the app for the payment to go through.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ExtAction {
+    ThreeDSecure(AcsChallenge),
+    /// Open the page, e.g. to confirm the payment by SMS.
+    Redirect(reqwest::Url),
+    /// Action of unknown kind, with the reported type if any.
+    Other(Option<String>),
+}
+
+/// Details of [`ProcessPaymentResponse::ExtAuthRequired`].
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ExtAuth {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub acs_uri: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub acs_params: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ext_action_uri: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ext_action_type: Option<String>,
+}
+
+impl ExtAuth {
+    #[must_use]
+    pub fn action(&self) -> ExtAction {
+        if let Some(uri) = &self.acs_uri {
+            return ExtAction::ThreeDSecure(AcsChallenge {
+                uri: uri.clone(),
+                params: self.acs_params.clone().unwrap_or(Value::Null),
+            });
+        }
+
+        match self
+            .ext_action_uri
+            .as_deref()
+            .and_then(|uri| reqwest::Url::parse(uri).ok())
+        {
+            Some(url) => ExtAction::Redirect(url),
+            None => ExtAction::Other(self.ext_action_type.clone()),
+        }
+    }
+}
+
 #[allow(clippy::large_enum_variant)]
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(tag = "status", rename_all = "snake_case")]
@@ -311,7 +1532,7 @@ pub enum ProcessPaymentResponse {
     Success(ProcessPaymentSuccessData),
     Refused { error: String },
     InProgress { next_retry: u64 },
-    ExtAuthRequired,
+    ExtAuthRequired(ExtAuth),
     AccountBlocked { account_unblock_uri: String },
 }
 
@@ -319,18 +1540,41 @@ pub enum ProcessPaymentResponse {
 pub enum ProcessPaymentError {
     Refused { error: String },
     InProgress { next_retry: u64 },
-    ExtAuthRequired,
+    ExtAuthRequired(ExtAuth),
     AccountBlocked { account_unblock_uri: String },
 }
 
 impl ProcessPaymentResponse {
+    /// Delay before the payment status should be checked again.
+    #[must_use]
+    pub fn next_retry_duration(&self) -> Option<Duration> {
+        match self {
+            Self::InProgress { next_retry } => Some(Duration::from_millis(*next_retry)),
+            _ => None,
+        }
+    }
+
+    /// Instant at which the payment status should be checked again, counted from `now`.
+    /// `None` if the delay reported is too large to represent.
+    #[must_use]
+    pub fn next_retry_after(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        self.next_retry_duration()
+            .and_then(|d| chrono::Duration::from_std(d).ok())
+            .and_then(|d| now.checked_add_signed(d))
+    }
+
+    #[must_use]
+    pub fn next_retry_at(&self) -> Option<DateTime<Utc>> {
+        self.next_retry_after(Utc::now())
+    }
+
     #[allow(clippy::missing_errors_doc)]
     pub fn into_result(self) -> Result<ProcessPaymentSuccessData, ProcessPaymentError> {
         Err(match self {
             Self::Success(data) => return Ok(data),
             Self::Refused { error } => ProcessPaymentError::Refused { error },
             Self::InProgress { next_retry } => ProcessPaymentError::InProgress { next_retry },
-            Self::ExtAuthRequired => ProcessPaymentError::ExtAuthRequired,
+            Self::ExtAuthRequired(ext_auth) => ProcessPaymentError::ExtAuthRequired(ext_auth),
             Self::AccountBlocked {
                 account_unblock_uri,
             } => ProcessPaymentError::AccountBlocked {
@@ -0,0 +1,142 @@
+use anyhow::bail;
+use bigdecimal::BigDecimal;
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
+use serde_with::{DeserializeFromStr, SerializeDisplay};
+use sha1::{Digest, Sha1};
+use std::{convert::Infallible, fmt::Display, str::FromStr};
+
+/// Source of an incoming payment notification.
+///
+/// The signature is computed over the same set of fields regardless of type.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, SerializeDisplay, DeserializeFromStr)]
+pub enum NotificationType {
+    /// Transfer from another YooMoney wallet.
+    P2PIncoming,
+    /// Payment from a bank card.
+    CardIncoming,
+    Unknown(String),
+}
+
+impl Display for NotificationType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::P2PIncoming => write!(f, "p2p-incoming"),
+            Self::CardIncoming => write!(f, "card-incoming"),
+            Self::Unknown(other) => write!(f, "{other}"),
+        }
+    }
+}
+
+impl FromStr for NotificationType {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "p2p-incoming" => Self::P2PIncoming,
+            "card-incoming" => Self::CardIncoming,
+            other => Self::Unknown(other.to_string()),
+        })
+    }
+}
+
+/// Algorithm a notification was signed with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum SignatureScheme {
+    /// SHA-1 over `&`-joined fields and the notification secret, sent in `sha1_hash`.
+    /// This is the only scheme YooMoney currently documents.
+    Sha1,
+}
+
+/// HTTP notification about an incoming payment, as sent to the merchant's webhook.
+///
+/// Fields that take part in the signature are kept exactly as received.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Notification {
+    pub notification_type: NotificationType,
+    pub operation_id: String,
+    pub amount: String,
+    #[serde(default)]
+    pub withdraw_amount: Option<String>,
+    pub currency: String,
+    pub datetime: String,
+    pub sender: String,
+    pub codepro: String,
+    pub label: String,
+    #[serde(default)]
+    pub sha1_hash: String,
+    #[serde(default)]
+    pub test_notification: Option<String>,
+    #[serde(default)]
+    pub unaccepted: Option<String>,
+}
+
+impl Notification {
+    #[allow(clippy::missing_errors_doc)]
+    pub fn amount(&self) -> anyhow::Result<BigDecimal> {
+        Ok(self.amount.parse()?)
+    }
+
+    #[allow(clippy::missing_errors_doc)]
+    pub fn datetime(&self) -> anyhow::Result<DateTime<Utc>> {
+        Ok(DateTime::parse_from_rfc3339(&self.datetime)?.with_timezone(&Utc))
+    }
+
+    #[must_use]
+    pub fn codepro(&self) -> bool {
+        self.codepro == "true"
+    }
+
+    /// Signature scheme used by the sender, detected by which hash field is present.
+    #[must_use]
+    pub fn signature_scheme(&self) -> Option<SignatureScheme> {
+        if self.sha1_hash.is_empty() {
+            None
+        } else {
+            Some(SignatureScheme::Sha1)
+        }
+    }
+
+    /// Expected `sha1_hash` for this notification given the notification secret.
+    #[must_use]
+    pub fn sha1_signature(&self, secret: &str) -> String {
+        let payload = [
+            &self.notification_type.to_string(),
+            &self.operation_id,
+            &self.amount,
+            &self.currency,
+            &self.datetime,
+            &self.sender,
+            &self.codepro,
+            secret,
+            &self.label,
+        ]
+        .join("&");
+
+        hex::encode(Sha1::digest(payload.as_bytes()))
+    }
+
+    /// Check that the notification was signed with `secret`, returning the scheme that validated.
+    #[allow(clippy::missing_errors_doc)]
+    pub fn verify(&self, secret: &str) -> anyhow::Result<SignatureScheme> {
+        match self.signature_scheme() {
+            Some(SignatureScheme::Sha1) => {
+                let expected = self.sha1_signature(secret);
+                if constant_time_eq(
+                    expected.as_bytes(),
+                    self.sha1_hash.to_ascii_lowercase().as_bytes(),
+                ) {
+                    Ok(SignatureScheme::Sha1)
+                } else {
+                    bail!("notification signature mismatch")
+                }
+            }
+            None => bail!("notification is not signed"),
+        }
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
@@ -0,0 +1,26 @@
+use http::StatusCode;
+use std::future::Future;
+use tracing::Span;
+
+tokio::task_local! {
+    static TRACE_PARENT: String;
+}
+
+/// Run `f` with a W3C `traceparent` that requests made inside it propagate to the server.
+pub async fn with_trace_context<F: Future>(traceparent: String, f: F) -> F::Output {
+    TRACE_PARENT.scope(traceparent, f).await
+}
+
+pub(crate) fn current_trace_parent() -> Option<String> {
+    TRACE_PARENT.try_with(Clone::clone).ok()
+}
+
+pub(crate) fn record_status(status: StatusCode) {
+    Span::current().record("http.status_code", status.as_u16());
+}
+
+pub(crate) fn record_error(e: &anyhow::Error) {
+    let span = Span::current();
+    span.record("otel.status_code", "ERROR");
+    span.record("error.message", tracing::field::display(e));
+}
@@ -0,0 +1,123 @@
+use crate::decimal_number;
+use bigdecimal::BigDecimal;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use thiserror::Error;
+
+/// Constraints of a single shop payment form field, as described by the showcase.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ShowcaseField {
+    pub name: String,
+    #[serde(default)]
+    pub required: bool,
+    /// Minimum value of a numeric field, e.g. `min_amount` of the sum.
+    #[serde(
+        default,
+        alias = "min_amount",
+        serialize_with = "decimal_number::serialize_option",
+        deserialize_with = "decimal_number::deserialize_option"
+    )]
+    pub min: Option<BigDecimal>,
+    #[serde(
+        default,
+        alias = "max_amount",
+        serialize_with = "decimal_number::serialize_option",
+        deserialize_with = "decimal_number::deserialize_option"
+    )]
+    pub max: Option<BigDecimal>,
+    /// Regular expression the whole value must match.
+    #[serde(default)]
+    pub pattern: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Showcase {
+    #[serde(default)]
+    pub title: String,
+    pub form: Vec<ShowcaseField>,
+}
+
+#[derive(Clone, Debug, Error)]
+pub enum ShowcaseValidationError {
+    #[error("required field {field} is missing")]
+    Missing { field: String },
+    #[error("field {field} is not a number")]
+    NotANumber { field: String },
+    #[error("field {field} is less than {min}")]
+    BelowMin { field: String, min: BigDecimal },
+    #[error("field {field} is greater than {max}")]
+    AboveMax { field: String, max: BigDecimal },
+    #[error("field {field} does not match {pattern}")]
+    PatternMismatch { field: String, pattern: String },
+    #[error("showcase pattern for field {field} is invalid: {pattern}")]
+    InvalidPattern { field: String, pattern: String },
+}
+
+impl ShowcaseField {
+    #[allow(clippy::missing_errors_doc)]
+    pub fn validate(&self, value: Option<&str>) -> Result<(), ShowcaseValidationError> {
+        let field = || self.name.clone();
+        let value = match value {
+            Some(v) => v,
+            None if self.required => {
+                return Err(ShowcaseValidationError::Missing { field: field() })
+            }
+            None => return Ok(()),
+        };
+
+        if self.min.is_some() || self.max.is_some() {
+            let number = value
+                .parse::<BigDecimal>()
+                .map_err(|_| ShowcaseValidationError::NotANumber { field: field() })?;
+            if let Some(min) = self.min.clone() {
+                if number < min {
+                    return Err(ShowcaseValidationError::BelowMin {
+                        field: field(),
+                        min,
+                    });
+                }
+            }
+            if let Some(max) = self.max.clone() {
+                if number > max {
+                    return Err(ShowcaseValidationError::AboveMax {
+                        field: field(),
+                        max,
+                    });
+                }
+            }
+        }
+
+        if let Some(pattern) = &self.pattern {
+            let re = Regex::new(&format!("^(?:{pattern})$")).map_err(|_| {
+                ShowcaseValidationError::InvalidPattern {
+                    field: field(),
+                    pattern: pattern.clone(),
+                }
+            })?;
+            if !re.is_match(value) {
+                return Err(ShowcaseValidationError::PatternMismatch {
+                    field: field(),
+                    pattern: pattern.clone(),
+                });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Showcase {
+    /// Check shop payment params against the form constraints before sending them to `request-payment`.
+    #[allow(clippy::missing_errors_doc)]
+    pub fn validate(
+        &self,
+        params: &HashMap<String, String>,
+    ) -> Result<(), ShowcaseValidationError> {
+        for field in &self.form {
+            field.validate(params.get(&field.name).map(String::as_str))?;
+        }
+
+        Ok(())
+    }
+}
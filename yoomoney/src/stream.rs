@@ -0,0 +1,185 @@
+use crate::{Operation, OperationStatus};
+use async_stream::try_stream;
+use bigdecimal::BigDecimal;
+use parking_lot::Mutex;
+use std::{
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+use tokio_stream::*;
+
+pub type ResultStream<T> = Pin<Box<dyn Stream<Item = anyhow::Result<T>> + Send>>;
+
+/// Result of [`Client::estimate_history_pages`](crate::Client::estimate_history_pages).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HistoryEstimate {
+    /// Operations on the first page.
+    pub first_page_size: usize,
+    /// Estimated number of pages, if it could be worked out.
+    pub pages: Option<u64>,
+}
+
+/// Progress of an operation history stream, updated as the stream is consumed.
+///
+/// Clones share the same counters, so a copy can be kept while the stream itself is moved away.
+#[derive(Clone, Debug, Default)]
+pub struct HistoryStats {
+    inner: Arc<HistoryCounters>,
+}
+
+#[derive(Debug, Default)]
+struct HistoryCounters {
+    pages: AtomicU64,
+    operations: AtomicU64,
+    next_record: Mutex<Option<u64>>,
+    resume_record: Mutex<Option<u64>>,
+}
+
+impl HistoryStats {
+    /// Pages fetched so far.
+    #[must_use]
+    pub fn pages(&self) -> u64 {
+        self.inner.pages.load(Ordering::Relaxed)
+    }
+
+    /// Operations successfully parsed and yielded so far.
+    #[must_use]
+    pub fn operations(&self) -> u64 {
+        self.inner.operations.load(Ordering::Relaxed)
+    }
+
+    /// Cursor of the page after the last one fetched, if there is one.
+    /// Can be passed as `start_record` to resume.
+    #[must_use]
+    pub fn next_record(&self) -> Option<u64> {
+        *self.inner.next_record.lock()
+    }
+
+    /// Cursor to pass as `start_record` to pick up where a dropped stream left off, or `None` once
+    /// the history was consumed to the end.
+    ///
+    /// It points at the page to fetch next: the first one whose operations were not all taken from
+    /// the stream. Operations of that page taken before the stream was dropped are yielded again on
+    /// resume, so processing should be idempotent. Keep a clone of the stats to read this after the
+    /// stream is dropped, e.g. on shutdown. With `ascending` set, it only advances at the very end.
+    #[must_use]
+    pub fn resume_record(&self) -> Option<u64> {
+        *self.inner.resume_record.lock()
+    }
+
+    pub(crate) fn record_resume(&self, resume_record: Option<u64>) {
+        *self.inner.resume_record.lock() = resume_record;
+    }
+
+    pub(crate) fn record_page(&self, next_record: Option<u64>) {
+        self.inner.pages.fetch_add(1, Ordering::Relaxed);
+        *self.inner.next_record.lock() = next_record;
+    }
+
+    pub(crate) fn record_operation(&self) {
+        self.inner.operations.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+pub trait ResultStreamExt<T>: Stream<Item = anyhow::Result<T>> + Send + Sized + 'static
+where
+    T: Send + 'static,
+{
+    /// Group items into batches of up to `n`. The last batch may be shorter.
+    ///
+    /// On error, the batch collected so far is yielded first, followed by the error.
+    fn chunks(self, n: usize) -> ResultStream<Vec<T>> {
+        assert!(n > 0, "chunk size must be non-zero");
+
+        Box::pin(try_stream! {
+            let mut inner = Box::pin(self);
+            let mut batch = Vec::with_capacity(n);
+            while let Some(res) = inner.next().await {
+                match res {
+                    Ok(v) => {
+                        batch.push(v);
+                        if batch.len() == n {
+                            yield std::mem::replace(&mut batch, Vec::with_capacity(n));
+                        }
+                    }
+                    Err(e) => {
+                        if !batch.is_empty() {
+                            yield std::mem::take(&mut batch);
+                        }
+                        Err(e)?;
+                    }
+                }
+            }
+
+            if !batch.is_empty() {
+                yield batch;
+            }
+        })
+    }
+}
+
+impl<S, T> ResultStreamExt<T> for S
+where
+    S: Stream<Item = anyhow::Result<T>> + Send + Sized + 'static,
+    T: Send + 'static,
+{
+}
+
+pub trait OperationStreamExt:
+    Stream<Item = anyhow::Result<Operation>> + Send + Sized + 'static
+{
+    /// End the stream right before the operation with the given id.
+    ///
+    /// Since history goes newest first, this yields only operations newer than `operation_id`.
+    /// If it never shows up, the stream runs to the end.
+    fn until_operation(self, operation_id: String) -> ResultStream<Operation> {
+        Box::pin(try_stream! {
+            let mut inner = Box::pin(self);
+            while let Some(op) = inner.next().await {
+                let op = op?;
+                if op.operation_id == operation_id {
+                    break;
+                }
+                yield op;
+            }
+        })
+    }
+
+    /// Walk operations oldest first, pairing each with the balance after it, starting from
+    /// `opening_balance` before the oldest one.
+    ///
+    /// With `newest_first`, as history is returned by default, the whole stream is buffered and
+    /// reversed before anything is yielded. Only successful operations change the balance.
+    fn with_running_balance(
+        self,
+        opening_balance: BigDecimal,
+        newest_first: bool,
+    ) -> ResultStream<(Operation, BigDecimal)> {
+        Box::pin(try_stream! {
+            let mut inner: ResultStream<Operation> = if newest_first {
+                let mut ops = self.collect::<anyhow::Result<Vec<_>>>().await?;
+                ops.reverse();
+                Box::pin(tokio_stream::iter(ops.into_iter().map(Ok)))
+            } else {
+                Box::pin(self)
+            };
+
+            let mut balance = opening_balance;
+            while let Some(op) = inner.next().await {
+                let op = op?;
+                if matches!(op.status, OperationStatus::Success) {
+                    balance += op.signed_amount();
+                }
+                yield (op, balance.clone());
+            }
+        })
+    }
+}
+
+impl<S> OperationStreamExt for S where
+    S: Stream<Item = anyhow::Result<Operation>> + Send + Sized + 'static
+{
+}
@@ -1,8 +1,22 @@
+use crate::{redact_body, Error, NoopSigner, RequestSigner, Signature};
 use anyhow::format_err;
-use http::StatusCode;
-use parking_lot::Mutex;
+use http::{
+    header::{ACCEPT_LANGUAGE, CONTENT_TYPE},
+    HeaderMap, HeaderValue, StatusCode,
+};
+use itertools::Itertools;
+use parking_lot::{Mutex, RwLock};
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, fmt::Debug, future::Future, pin::Pin, sync::Arc};
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fmt::Debug,
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
 use tracing::*;
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -21,6 +35,34 @@ impl<T> Rsp<T> {
     }
 }
 
+#[derive(Clone, Copy, Debug)]
+pub struct CallStats {
+    pub latency: Duration,
+    pub body_len: usize,
+}
+
+#[derive(Clone, Debug)]
+pub struct Timed<T> {
+    pub value: T,
+    pub stats: CallStats,
+}
+
+pub type CallFuture<T> = Pin<Box<dyn Future<Output = anyhow::Result<T>> + Send + 'static>>;
+
+/// Response metadata for callers that need more than the parsed body.
+#[derive(Clone, Debug, Default)]
+pub struct CallContext {
+    pub headers: HeaderMap,
+}
+
+impl CallContext {
+    /// Value of a header, if present and valid UTF-8.
+    #[must_use]
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name).and_then(|v| v.to_str().ok())
+    }
+}
+
 pub trait Transport: Debug + Send + Sync + 'static {
     fn call(
         &self,
@@ -33,50 +75,426 @@ pub trait Transport: Debug + Send + Sync + 'static {
         endpoint: &'static str,
         params: &HashMap<&str, String>,
     ) -> Pin<Box<dyn Future<Output = anyhow::Result<String>> + Send + 'static>>;
+
+    /// Same as [`call`](Self::call), but also returns response headers.
+    /// Transports without the notion of headers return an empty map.
+    fn call_with_headers(
+        &self,
+        endpoint: &'static str,
+        params: &HashMap<&str, String>,
+    ) -> CallFuture<(String, HeaderMap)> {
+        let c = self.call(endpoint, params);
+        Box::pin(async move { Ok((c.await?, HeaderMap::new())) })
+    }
+
+    /// Copy of this transport authorizing requests with another token.
+    fn with_bearer(&self, _bearer: String) -> anyhow::Result<Arc<dyn Transport>> {
+        Err(format_err!("transport does not support overriding bearer"))
+    }
+
+    /// Authorize subsequent requests with another token, e.g. after a refresh.
+    fn set_bearer(&self, _bearer: String) -> anyhow::Result<()> {
+        Err(format_err!("transport does not support changing bearer"))
+    }
+
+    /// Base address requests are sent to, if this transport has one.
+    fn base_url(&self) -> Option<String> {
+        None
+    }
+
+    /// Redirect subsequent requests to another base address.
+    fn set_base_url(&self, _url: String) -> anyhow::Result<()> {
+        Err(format_err!("transport does not support changing base URL"))
+    }
+}
+
+/// Settings applied to every HTTP client created by [`RemoteCaller`], including the one used for redirects.
+#[derive(Clone, Debug)]
+pub struct HttpOptions {
+    pub user_agent: String,
+    pub retry_policy: RetryPolicy,
+    /// Responses with larger bodies are rejected with [`Error::ResponseTooLarge`].
+    pub max_response_bytes: usize,
+    /// Request compressed responses and decompress them transparently.
+    pub compression: bool,
+    /// Overall timeout for a request, from connecting until the response body is read.
+    pub request_timeout: Option<Duration>,
+    /// Timeout for establishing a connection only.
+    pub connect_timeout: Option<Duration>,
+    /// `Accept-Language` sent with every request, selecting the language of server messages and titles.
+    pub language: String,
+    pub signer: Arc<dyn RequestSigner>,
+    /// How params are sent, unless overridden for the endpoint in `endpoint_body_encodings`.
+    pub body_encoding: BodyEncoding,
+    /// Body encoding of particular endpoints, keyed by path such as `api/account-info`.
+    pub endpoint_body_encodings: HashMap<String, BodyEncoding>,
+    /// Skip TLS certificate verification.
+    ///
+    /// **DANGEROUS**: this makes connections trivially interceptable. Only use against local mock servers.
+    #[cfg(feature = "dangerous")]
+    pub danger_accept_invalid_certs: bool,
+}
+
+/// Retries of requests that failed to connect, timed out or were rejected as overloaded.
+///
+/// Delay doubles with every attempt, starting with `base_delay` and capped at `max_delay`.
+#[derive(Clone, Copy, Debug)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 0,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Delay before the given retry attempt, counting from 1.
+    #[must_use]
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        self.base_delay
+            .saturating_mul(2_u32.saturating_pow(attempt.saturating_sub(1)))
+            .min(self.max_delay)
+    }
+}
+
+fn is_retryable_status(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS || status == StatusCode::SERVICE_UNAVAILABLE
+}
+
+/// API responses are JSON, so an HTML page means the service is down for maintenance.
+///
+/// Only successful and server error statuses are considered, so that e.g. a rejected token
+/// is reported as such whatever page comes with it.
+fn is_maintenance_page(status: StatusCode, headers: &HeaderMap, body: &str) -> bool {
+    (status.is_success() || status.is_server_error())
+        && (headers
+            .get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| v.starts_with("text/html"))
+            || body.trim_start().starts_with('<'))
+}
+
+pub const LIBRARY_USER_AGENT: &str = concat!("yoomoney-rs/", env!("CARGO_PKG_VERSION"));
+
+/// User agent identifying the calling application in front of the library, e.g. `MyApp/1.2 yoomoney-rs/0.1.0`.
+#[must_use]
+pub fn user_agent(app_name: Option<&str>, app_version: Option<&str>) -> String {
+    match (app_name, app_version) {
+        (Some(name), Some(version)) => format!("{name}/{version} {LIBRARY_USER_AGENT}"),
+        (Some(name), None) => format!("{name} {LIBRARY_USER_AGENT}"),
+        (None, _) => LIBRARY_USER_AGENT.to_string(),
+    }
+}
+
+/// Language of server messages unless set otherwise, same as the API's own default.
+pub const DEFAULT_LANGUAGE: &str = "ru";
+
+pub const DEFAULT_MAX_RESPONSE_BYTES: usize = 16 * 1024 * 1024;
+
+/// Read response body, failing as soon as it grows past `limit`.
+async fn read_body(mut rsp: reqwest::Response, limit: usize) -> anyhow::Result<String> {
+    if rsp.content_length().is_some_and(|len| len > limit as u64) {
+        return Err(Error::ResponseTooLarge { limit }.into());
+    }
+
+    let mut data = Vec::new();
+    while let Some(chunk) = rsp.chunk().await? {
+        if data.len() + chunk.len() > limit {
+            return Err(Error::ResponseTooLarge { limit }.into());
+        }
+        data.extend_from_slice(&chunk);
+    }
+
+    Ok(String::from_utf8(data)?)
+}
+
+fn default_user_agent() -> String {
+    user_agent(None, None)
+}
+
+impl Default for HttpOptions {
+    fn default() -> Self {
+        Self {
+            user_agent: default_user_agent(),
+            retry_policy: RetryPolicy::default(),
+            max_response_bytes: DEFAULT_MAX_RESPONSE_BYTES,
+            compression: true,
+            request_timeout: None,
+            connect_timeout: None,
+            language: DEFAULT_LANGUAGE.to_string(),
+            signer: Arc::new(NoopSigner),
+            body_encoding: BodyEncoding::default(),
+            endpoint_body_encodings: HashMap::new(),
+            #[cfg(feature = "dangerous")]
+            danger_accept_invalid_certs: false,
+        }
+    }
+}
+
+/// How request params are put in the body, with `Content-Type` set to match.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BodyEncoding {
+    /// `application/x-www-form-urlencoded`, as expected by most of the API.
+    #[default]
+    Form,
+    /// `application/json` object with string values, for endpoints that require it.
+    Json,
+}
+
+impl HttpOptions {
+    /// Body encoding used for `endpoint`.
+    #[must_use]
+    pub fn body_encoding_for(&self, endpoint: &str) -> BodyEncoding {
+        self.endpoint_body_encodings
+            .get(endpoint)
+            .copied()
+            .unwrap_or(self.body_encoding)
+    }
+
+    pub fn apply(&self, builder: reqwest::ClientBuilder) -> reqwest::ClientBuilder {
+        let mut builder = builder
+            .user_agent(&self.user_agent)
+            .gzip(self.compression)
+            .brotli(self.compression)
+            .deflate(self.compression);
+        if let Some(timeout) = self.request_timeout {
+            builder = builder.timeout(timeout);
+        }
+        if let Some(timeout) = self.connect_timeout {
+            builder = builder.connect_timeout(timeout);
+        }
+        match HeaderValue::from_str(&self.language) {
+            Ok(language) => {
+                builder =
+                    builder.default_headers(HeaderMap::from_iter([(ACCEPT_LANGUAGE, language)]));
+            }
+            Err(_) => warn!("Ignoring invalid language {:?}", self.language),
+        }
+        #[cfg(feature = "dangerous")]
+        if self.danger_accept_invalid_certs {
+            warn!("TLS certificate verification is disabled");
+            builder = builder.danger_accept_invalid_certs(true);
+        }
+
+        builder
+    }
+}
+
+/// Attach `params` as body in `encoding`, along with whatever `signer` adds for them.
+fn signed_body(
+    req: reqwest::RequestBuilder,
+    signer: &dyn RequestSigner,
+    encoding: BodyEncoding,
+    endpoint: &str,
+    params: &HashMap<&str, String>,
+) -> reqwest::RequestBuilder {
+    let Signature {
+        headers,
+        params: extra,
+    } = signer.sign(endpoint, params);
+    let mut body = params
+        .iter()
+        .map(|(k, v)| (*k, v.as_str()))
+        .collect::<HashMap<_, _>>();
+    body.extend(extra.iter().map(|(k, v)| (k.as_str(), v.as_str())));
+
+    let req = req.headers(headers);
+    match encoding {
+        BodyEncoding::Form => req.form(&body),
+        BodyEncoding::Json => req.json(&body),
+    }
 }
 
 #[derive(Debug)]
 pub struct RemoteCaller {
     pub http_client: reqwest::Client,
-    pub addr: String,
-    pub bearer: Option<String>,
+    pub addr: RwLock<String>,
+    pub bearer: RwLock<Option<String>>,
+    pub options: HttpOptions,
 }
 
 impl Transport for RemoteCaller {
+    fn with_bearer(&self, bearer: String) -> anyhow::Result<Arc<dyn Transport>> {
+        Ok(Arc::new(Self {
+            // Cloned client shares the connection pool
+            http_client: self.http_client.clone(),
+            addr: RwLock::new(self.addr.read().clone()),
+            bearer: RwLock::new(Some(bearer)),
+            options: self.options.clone(),
+        }))
+    }
+
+    fn set_bearer(&self, bearer: String) -> anyhow::Result<()> {
+        *self.bearer.write() = Some(bearer);
+        Ok(())
+    }
+
+    fn base_url(&self) -> Option<String> {
+        Some(self.addr.read().clone())
+    }
+
+    fn set_base_url(&self, url: String) -> anyhow::Result<()> {
+        *self.addr.write() = url;
+        Ok(())
+    }
+
     fn call(
         &self,
         endpoint: &'static str,
         params: &HashMap<&str, String>,
     ) -> Pin<Box<dyn Future<Output = anyhow::Result<String>> + Send + 'static>> {
+        let c = self.call_with_headers(endpoint, params);
+        Box::pin(async move { Ok(c.await?.0) })
+    }
+
+    fn call_with_headers(
+        &self,
+        endpoint: &'static str,
+        params: &HashMap<&str, String>,
+    ) -> CallFuture<(String, HeaderMap)> {
+        let bearer = self.bearer.read().clone();
+        if endpoint.starts_with("api/") && bearer.is_none() {
+            return Box::pin(async move { Err(Error::NotAuthorized { endpoint }.into()) });
+        }
+
         let client = self.http_client.clone();
-        let uri = format!("{}/{}", self.addr, endpoint);
+        let max_response_bytes = self.options.max_response_bytes;
+        let uri = format!("{}/{}", self.addr.read(), endpoint);
         let params_trace = format!("{params:?}");
 
-        let mut req = client.post(uri).form(params);
-        if let Some(bearer) = self.bearer.as_ref() {
+        let retry_policy = self.options.retry_policy;
+        #[cfg(not(feature = "otel"))]
+        let span = debug_span!(
+            "call",
+            endpoint,
+            request_id = params.get("request_id").map(String::as_str),
+            status = field::Empty,
+            latency_ms = field::Empty,
+        );
+        #[cfg(feature = "otel")]
+        let span = debug_span!(
+            "call",
+            endpoint,
+            request_id = params.get("request_id").map(String::as_str),
+            status = field::Empty,
+            latency_ms = field::Empty,
+            otel.kind = "client",
+            http.method = "POST",
+            http.url = %uri,
+            http.status_code = field::Empty,
+            otel.status_code = field::Empty,
+            error.message = field::Empty,
+        );
+
+        let mut req = signed_body(
+            client.post(uri),
+            self.options.signer.as_ref(),
+            self.options.body_encoding_for(endpoint),
+            endpoint,
+            params,
+        );
+        if let Some(bearer) = bearer {
             req = req.bearer_auth(bearer);
         }
+        // Field names only: values may contain tokens and personal data
+        let field_names = params.keys().copied().sorted().join(",");
+        let body_len = req
+            .try_clone()
+            .and_then(|req| req.build().ok())
+            .and_then(|req| req.body().and_then(|body| body.as_bytes()).map(<[u8]>::len));
+        #[cfg(feature = "otel")]
+        if let Some(traceparent) = crate::otel::current_trace_parent() {
+            req = req.header("traceparent", traceparent);
+        }
 
-        Box::pin(async move {
+        let fut = async move {
+            debug!(fields = %field_names, body_len, "Prepared request body");
             trace!(
                 "Sending request to endpoint {} with params: {}",
                 endpoint,
                 params_trace
             );
 
-            let rsp = req.send().await?;
-            let err = rsp.error_for_status_ref().err();
+            let started = Instant::now();
+            let mut attempt = 0;
+            let (status, err, headers, data) = loop {
+                let can_retry = attempt < retry_policy.max_retries;
+                let error = match req
+                    .try_clone()
+                    .expect("form and JSON bodies are always clonable; qed")
+                    .send()
+                    .await
+                {
+                    Ok(rsp) if can_retry && is_retryable_status(rsp.status()) => {
+                        format!("status {}", rsp.status())
+                    }
+                    Ok(rsp) => {
+                        #[cfg(feature = "otel")]
+                        crate::otel::record_status(rsp.status());
+                        let status_code = rsp.status();
+                        let status = status_code.as_u16();
+                        let err = rsp.error_for_status_ref().err();
+                        let headers = rsp.headers().clone();
+                        let data = read_body(rsp, max_response_bytes).await?;
 
-            let data = rsp.text().await?;
+                        if !is_maintenance_page(status_code, &headers, &data) {
+                            break (status, err, headers, data);
+                        }
+                        if !can_retry {
+                            return Err(Error::Maintenance { status }.into());
+                        }
+                        format!("maintenance page with status {status}")
+                    }
+                    Err(e) if can_retry && (e.is_connect() || e.is_timeout()) => e.to_string(),
+                    Err(e) => return Err(e.into()),
+                };
 
+                attempt += 1;
+                let delay = retry_policy.backoff(attempt);
+                warn!(attempt, %error, ?delay, "Request failed, retrying");
+                tokio::time::sleep(delay).await;
+            };
+            Span::current().record("status", status);
+
+            let latency_ms = u64::try_from(started.elapsed().as_millis()).unwrap_or(u64::MAX);
+            Span::current().record("latency_ms", latency_ms);
+            debug!(
+                status,
+                latency_ms,
+                body_len = data.len(),
+                "Received response"
+            );
             trace!("Received HTTP response: {}", data);
 
+            if status == StatusCode::UNAUTHORIZED.as_u16() {
+                return Err(Error::InvalidToken { endpoint }.into());
+            }
             if let Some(err) = err {
                 return Err(format_err!("Received error {} with data: {}", err, data));
             }
 
-            Ok(data)
-        })
+            Ok((data, headers))
+        };
+
+        Box::pin(
+            async move {
+                let res = fut.await;
+                #[cfg(feature = "otel")]
+                if let Err(e) = &res {
+                    crate::otel::record_error(e);
+                }
+                res
+            }
+            .instrument(span),
+        )
     }
 
     fn get_redirect(
@@ -84,10 +502,12 @@ impl Transport for RemoteCaller {
         endpoint: &'static str,
         params: &HashMap<&str, String>,
     ) -> Pin<Box<dyn Future<Output = anyhow::Result<String>> + Send + 'static>> {
-        let uri = format!("{}/{}", self.addr, endpoint);
+        let uri = format!("{}/{}", self.addr.read(), endpoint);
 
         let redirect_url = Arc::new(Mutex::new(None));
-        let client = reqwest::Client::builder()
+        let client = self
+            .options
+            .apply(reqwest::Client::builder())
             .redirect(reqwest::redirect::Policy::custom({
                 let redirect_url = redirect_url.clone();
                 move |attempt| {
@@ -96,7 +516,16 @@ impl Transport for RemoteCaller {
                 }
             }))
             .build()
-            .map(|client| client.post(&uri).form(params));
+            .map(|client| {
+                // OAuth endpoints only take forms
+                signed_body(
+                    client.post(&uri),
+                    self.options.signer.as_ref(),
+                    BodyEncoding::Form,
+                    endpoint,
+                    params,
+                )
+            });
 
         let params_trace = format!("{params:?}");
 
@@ -107,22 +536,114 @@ impl Transport for RemoteCaller {
                 params_trace
             );
 
-            let client = client?;
+            let client = client.map_err(Error::RedirectClientBuild)?;
             let rsp = client.send().await?;
 
-            match rsp.status() {
-                StatusCode::FOUND => Ok((*redirect_url.lock())
-                    .clone()
-                    .expect("always filled by redirect policy; qed")),
-                other => Err(format_err!("Unexpected status code: {}", other)),
+            // Redirect policy is only consulted when there is a `Location` to follow
+            let redirect_url = redirect_url.lock().take();
+            match (rsp.status(), redirect_url) {
+                (StatusCode::FOUND, Some(redirect_url)) => Ok(redirect_url),
+                (status, _) => Err(Error::RedirectMissing {
+                    status: status.as_u16(),
+                }
+                .into()),
             }
         })
     }
 }
 
+/// How response parsing treats data this library does not know about.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DeserializeMode {
+    /// Ignore unknown fields and map unknown enum values to their `Unknown` variants.
+    #[default]
+    Lenient,
+    /// Fail with [`Error::UnexpectedResponse`] on unknown fields and enum values, e.g. to catch API
+    /// changes in CI.
+    ///
+    /// Fields nested in untagged or status-tagged enums are buffered by serde before being
+    /// matched, so unknown ones among them go unnoticed.
+    Strict,
+}
+
+thread_local! {
+    static FALLBACKS: RefCell<Option<Vec<String>>> = const { RefCell::new(None) };
+}
+
+/// Record that a lenient fallback was taken while parsing, so that strict parsing can reject it.
+pub(crate) fn note_fallback(what: impl FnOnce() -> String) {
+    FALLBACKS.with(|fallbacks| {
+        if let Some(fallbacks) = fallbacks.borrow_mut().as_mut() {
+            fallbacks.push(what());
+        }
+    });
+}
+
+fn parse_body<T>(endpoint: &'static str, body: &str, mode: DeserializeMode) -> anyhow::Result<T>
+where
+    T: for<'de> Deserialize<'de>,
+{
+    let deserialize_error = |source| Error::Deserialize {
+        endpoint,
+        source,
+        body: redact_body(body),
+    };
+    if mode == DeserializeMode::Lenient {
+        return Ok(serde_json::from_str(body).map_err(deserialize_error)?);
+    }
+
+    // Parsing is synchronous, so nothing else runs on this thread in between
+    FALLBACKS.with(|fallbacks| *fallbacks.borrow_mut() = Some(Vec::new()));
+    let mut unexpected = Vec::new();
+    let mut de = serde_json::Deserializer::from_str(body);
+    let res = serde_ignored::deserialize(&mut de, |path| {
+        unexpected.push(format!("unknown field {path}"));
+    })
+    .and_then(|v| de.end().map(|()| v));
+    unexpected.extend(
+        FALLBACKS
+            .with(|fallbacks| fallbacks.borrow_mut().take())
+            .unwrap_or_default(),
+    );
+    let v = res.map_err(deserialize_error)?;
+    if !unexpected.is_empty() {
+        return Err(Error::UnexpectedResponse {
+            endpoint,
+            details: unexpected,
+        }
+        .into());
+    }
+
+    Ok(v)
+}
+
+/// Parse an [`Rsp`]. Its untagged payload is buffered before being matched, so in strict mode
+/// a successful response is parsed once more on its own to check it.
+fn parse_rsp<T>(endpoint: &'static str, body: &str, mode: DeserializeMode) -> anyhow::Result<Rsp<T>>
+where
+    T: for<'de> Deserialize<'de>,
+{
+    match parse_body(endpoint, body, DeserializeMode::Lenient)? {
+        Rsp::OK(_) if mode == DeserializeMode::Strict => {
+            Ok(Rsp::OK(parse_body(endpoint, body, mode)?))
+        }
+        rsp => Ok(rsp),
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct CallerWrapper {
     pub transport: Arc<dyn Transport>,
+    /// Caps requests in flight across everything sharing this caller.
+    pub limiter: Option<Arc<Semaphore>>,
+    pub mode: DeserializeMode,
+}
+
+async fn acquire(limiter: Option<Arc<Semaphore>>) -> Option<OwnedSemaphorePermit> {
+    match limiter {
+        Some(limiter) => limiter.acquire_owned().await.ok(),
+        None => None,
+    }
 }
 
 impl CallerWrapper {
@@ -134,8 +655,79 @@ impl CallerWrapper {
     where
         T: for<'de> Deserialize<'de> + Send + 'static,
     {
+        let permit = acquire(self.limiter.clone());
+        let c = self.transport.call(method, params);
+        let mode = self.mode;
+        async move {
+            let _permit = permit.await;
+            parse_rsp(method, &c.await?, mode)
+        }
+    }
+
+    /// Same as [`call`](Self::call), but for endpoints reporting failures through their own `status`
+    /// variants, which would otherwise be swallowed by [`Rsp::Error`].
+    pub fn call_plain<T>(
+        &self,
+        method: &'static str,
+        params: &HashMap<&str, String>,
+    ) -> impl Future<Output = anyhow::Result<T>> + Send + 'static
+    where
+        T: for<'de> Deserialize<'de> + Send + 'static,
+    {
+        let permit = acquire(self.limiter.clone());
+        let c = self.transport.call(method, params);
+        let mode = self.mode;
+        async move {
+            let _permit = permit.await;
+            parse_body(method, &c.await?, mode)
+        }
+    }
+
+    /// Same as [`call`](Self::call), but also reports how long the call took and how big the response was.
+    pub fn call_timed<T>(
+        &self,
+        method: &'static str,
+        params: &HashMap<&str, String>,
+    ) -> impl Future<Output = anyhow::Result<Timed<Rsp<T>>>> + Send + 'static
+    where
+        T: for<'de> Deserialize<'de> + Send + 'static,
+    {
+        let permit = acquire(self.limiter.clone());
         let c = self.transport.call(method, params);
-        async move { Ok(serde_json::from_str(&c.await?)?) }
+        let mode = self.mode;
+        async move {
+            let _permit = permit.await;
+            let started = Instant::now();
+            let data = c.await?;
+            let stats = CallStats {
+                latency: started.elapsed(),
+                body_len: data.len(),
+            };
+
+            Ok(Timed {
+                value: parse_rsp(method, &data, mode)?,
+                stats,
+            })
+        }
+    }
+
+    /// Same as [`call`](Self::call), but also returns response headers.
+    pub fn call_with_context<T>(
+        &self,
+        method: &'static str,
+        params: &HashMap<&str, String>,
+    ) -> impl Future<Output = anyhow::Result<(Rsp<T>, CallContext)>> + Send + 'static
+    where
+        T: for<'de> Deserialize<'de> + Send + 'static,
+    {
+        let permit = acquire(self.limiter.clone());
+        let c = self.transport.call_with_headers(method, params);
+        let mode = self.mode;
+        async move {
+            let _permit = permit.await;
+            let (data, headers) = c.await?;
+            Ok((parse_rsp(method, &data, mode)?, CallContext { headers }))
+        }
     }
 
     pub fn call_empty(
@@ -143,9 +735,11 @@ impl CallerWrapper {
         method: &'static str,
         params: &HashMap<&str, String>,
     ) -> impl Future<Output = anyhow::Result<()>> + Send + 'static {
+        let permit = acquire(self.limiter.clone());
         let c = self.transport.call(method, params);
 
         async move {
+            let _permit = permit.await;
             c.await?;
 
             Ok(())
@@ -157,8 +751,11 @@ impl CallerWrapper {
         endpoint: &'static str,
         params: &HashMap<&str, String>,
     ) -> impl Future<Output = anyhow::Result<String>> + Send + 'static {
-        let s = self.transport.get_redirect(endpoint, params);
-
-        s
+        let permit = acquire(self.limiter.clone());
+        let c = self.transport.get_redirect(endpoint, params);
+        async move {
+            let _permit = permit.await;
+            c.await
+        }
     }
 }
@@ -0,0 +1,111 @@
+use serde_json::json;
+use std::{collections::HashMap, future::Future, pin::Pin, sync::Arc};
+use yoomoney::*;
+
+/// Authorizes every request, remembering the `instance_name` of each.
+#[derive(Debug, Default)]
+struct OAuthTransport {
+    instance_names: parking_lot::Mutex<Vec<String>>,
+}
+
+impl Transport for OAuthTransport {
+    fn call(
+        &self,
+        endpoint: &'static str,
+        _params: &HashMap<&str, String>,
+    ) -> Pin<Box<dyn Future<Output = anyhow::Result<String>> + Send + 'static>> {
+        assert_eq!(endpoint, "oauth/token");
+        let rsp = json!({ "access_token": "token" }).to_string();
+        Box::pin(async move { Ok(rsp) })
+    }
+
+    fn get_redirect(
+        &self,
+        endpoint: &'static str,
+        params: &HashMap<&str, String>,
+    ) -> Pin<Box<dyn Future<Output = anyhow::Result<String>> + Send + 'static>> {
+        assert_eq!(endpoint, "oauth/authorize");
+        self.instance_names
+            .lock()
+            .push(params["instance_name"].clone());
+        Box::pin(async move { Ok("https://example.com/authorize".to_string()) })
+    }
+}
+
+async fn authorize(
+    transport: &Arc<OAuthTransport>,
+    previous: &StoredCredentials,
+    returned_state: Option<&str>,
+) -> anyhow::Result<StoredCredentials> {
+    let returned_state = returned_state.map(str::to_string);
+    UnauthorizedClient::from_transport(
+        transport.clone(),
+        "client".into(),
+        "https://example.com".into(),
+    )
+    .reauthorize(
+        previous,
+        AuthorizeOptions {
+            state: Some("state".into()),
+            ..[AccessScope::AccountInfo]
+                .into_iter()
+                .collect::<std::collections::HashSet<_>>()
+                .into()
+        },
+        move |_| {
+            let state = returned_state.clone();
+            async move {
+                Ok::<_, anyhow::Error>(AuthorizationResponse {
+                    code: "code".into(),
+                    state,
+                })
+            }
+        },
+    )
+    .await
+}
+
+async fn login(transport: &Arc<OAuthTransport>, previous: &StoredCredentials) -> StoredCredentials {
+    authorize(transport, previous, Some("state")).await.unwrap()
+}
+
+#[tokio::test]
+async fn reauthorization_keeps_instance() {
+    let transport = Arc::new(OAuthTransport::default());
+
+    let first = login(&transport, &StoredCredentials::default()).await;
+    assert_eq!(first.access_token, "token");
+    assert_eq!(first.scope.as_deref(), Some("account-info"));
+    let instance_name = first.instance_name.clone().unwrap();
+
+    let second = login(&transport, &first).await;
+    assert_eq!(
+        second.instance_name.as_deref(),
+        Some(instance_name.as_str())
+    );
+    assert_eq!(
+        *transport.instance_names.lock(),
+        [instance_name.clone(), instance_name]
+    );
+}
+
+#[tokio::test]
+async fn state_is_checked() {
+    let transport = Arc::new(OAuthTransport::default());
+
+    let err = authorize(&transport, &StoredCredentials::default(), Some("forged"))
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<Error>(),
+        Some(Error::StateMismatch { expected, actual }) if expected == "state" && actual == "forged"
+    ));
+
+    let err = authorize(&transport, &StoredCredentials::default(), None)
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<Error>(),
+        Some(Error::StateMissing { expected }) if expected == "state"
+    ));
+}
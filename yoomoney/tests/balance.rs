@@ -0,0 +1,73 @@
+use serde_json::json;
+use std::{
+    collections::HashMap,
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio_stream::StreamExt;
+use yoomoney::*;
+
+/// Serves account info with the next of `balances` on every call, repeating the last one.
+#[derive(Debug)]
+struct BalanceTransport {
+    balances: Vec<&'static str>,
+    calls: AtomicUsize,
+}
+
+impl Transport for BalanceTransport {
+    fn call(
+        &self,
+        endpoint: &'static str,
+        _params: &HashMap<&str, String>,
+    ) -> Pin<Box<dyn Future<Output = anyhow::Result<String>> + Send + 'static>> {
+        assert_eq!(endpoint, "api/account-info");
+        let call = self.calls.fetch_add(1, Ordering::SeqCst);
+        let balance = self.balances[call.min(self.balances.len() - 1)];
+        let rsp = json!({
+            "account": "4100123",
+            "balance": balance,
+            "account_status": "named",
+            "account_type": "personal",
+            "cards_linked": [],
+        })
+        .to_string();
+        Box::pin(async move { Ok(rsp) })
+    }
+
+    fn get_redirect(
+        &self,
+        _endpoint: &'static str,
+        _params: &HashMap<&str, String>,
+    ) -> Pin<Box<dyn Future<Output = anyhow::Result<String>> + Send + 'static>> {
+        unimplemented!()
+    }
+}
+
+fn client() -> Client {
+    Client::from_transport(Arc::new(BalanceTransport {
+        balances: vec!["10", "10", "15.5", "15.5", "3"],
+        calls: AtomicUsize::new(0),
+    }))
+}
+
+async fn balances(stream: ResultStream<bigdecimal::BigDecimal>, n: usize) -> Vec<String> {
+    stream
+        .take(n)
+        .map(|balance| balance.unwrap().to_string())
+        .collect()
+        .await
+}
+
+#[tokio::test]
+async fn balance_stream() {
+    let every = client().balance_stream(Duration::from_millis(1), false);
+    assert_eq!(balances(every, 4).await, ["10", "10", "15.5", "15.5"]);
+
+    let distinct = client().balance_stream(Duration::from_millis(1), true);
+    assert_eq!(balances(distinct, 3).await, ["10", "15.5", "3"]);
+}
@@ -0,0 +1,589 @@
+use bigdecimal::BigDecimal;
+use serde_json::json;
+use std::{collections::HashMap, future::Future, pin::Pin, sync::Arc};
+use tokio_stream::StreamExt;
+use yoomoney::*;
+
+#[derive(Debug)]
+struct MockTransport {
+    responses: HashMap<&'static str, serde_json::Value>,
+}
+
+impl Transport for MockTransport {
+    fn call(
+        &self,
+        endpoint: &'static str,
+        _params: &HashMap<&str, String>,
+    ) -> Pin<Box<dyn Future<Output = anyhow::Result<String>> + Send + 'static>> {
+        let rsp = self.responses[endpoint].to_string();
+        Box::pin(async move { Ok(rsp) })
+    }
+
+    fn get_redirect(
+        &self,
+        _endpoint: &'static str,
+        _params: &HashMap<&str, String>,
+    ) -> Pin<Box<dyn Future<Output = anyhow::Result<String>> + Send + 'static>> {
+        unimplemented!()
+    }
+}
+
+fn operation(id: &str) -> serde_json::Value {
+    json!({
+        "operation_id": id,
+        "status": "success",
+        "datetime": "2020-01-01T00:00:00Z",
+        "title": "Test",
+        "pattern_id": null,
+        "direction": "in",
+        "amount": "1.00",
+        "type": "deposition",
+    })
+}
+
+fn client(operations: Vec<serde_json::Value>) -> Client {
+    Client::from_transport(Arc::new(MockTransport {
+        responses: [("api/operation-history", json!({ "operations": operations }))]
+            .into_iter()
+            .collect(),
+    }))
+}
+
+#[tokio::test]
+async fn malformed_operation_does_not_abort_page() {
+    let client = client(vec![
+        operation("1"),
+        json!({ "operation_id": "2" }),
+        operation("3"),
+    ]);
+
+    let res = client
+        .operation_history(Default::default(), None, None, None, 0, false, false)
+        .collect::<Vec<_>>()
+        .await;
+
+    assert_eq!(res.len(), 3);
+    assert_eq!(res[0].as_ref().unwrap().operation_id, "1");
+    assert!(res[1]
+        .as_ref()
+        .unwrap_err()
+        .downcast_ref::<Error>()
+        .is_some());
+    assert_eq!(res[2].as_ref().unwrap().operation_id, "3");
+}
+
+#[tokio::test]
+async fn pending_incoming_transfers() {
+    let mut op = operation("1");
+    op["type"] = json!("incoming-transfer-protected");
+    let client = client(vec![op]);
+
+    let pending = client.pending_incoming_transfers().await.unwrap();
+
+    assert_eq!(pending.len(), 1);
+    assert!(matches!(
+        pending[0].operation_type,
+        RspOperationType::IncomingTransferProtected
+    ));
+}
+
+#[tokio::test]
+async fn history_until_operation() {
+    let client = client(vec![operation("3"), operation("2"), operation("1")]);
+
+    let ids = |ops: Vec<anyhow::Result<Operation>>| {
+        ops.into_iter()
+            .map(|op| op.unwrap().operation_id)
+            .collect::<Vec<_>>()
+    };
+
+    let ops = client
+        .operation_history_until("2".into(), false)
+        .collect::<Vec<_>>()
+        .await;
+    assert_eq!(ids(ops), ["3"]);
+
+    let ops = client
+        .operation_history_until("0".into(), false)
+        .collect::<Vec<_>>()
+        .await;
+    assert_eq!(ids(ops), ["3", "2", "1"]);
+}
+
+/// Serves `pages` of operations in order, keyed by `start-record`.
+#[derive(Debug)]
+struct PagedTransport {
+    pages: Vec<Vec<serde_json::Value>>,
+}
+
+impl Transport for PagedTransport {
+    fn call(
+        &self,
+        _endpoint: &'static str,
+        params: &HashMap<&str, String>,
+    ) -> Pin<Box<dyn Future<Output = anyhow::Result<String>> + Send + 'static>> {
+        let page = params["start-record"].parse::<usize>().unwrap();
+        let mut rsp = json!({ "operations": self.pages.get(page).cloned().unwrap_or_default() });
+        if page + 1 < self.pages.len() {
+            rsp["next_record"] = json!((page + 1).to_string());
+        }
+        let rsp = rsp.to_string();
+        Box::pin(async move { Ok(rsp) })
+    }
+
+    fn get_redirect(
+        &self,
+        _endpoint: &'static str,
+        _params: &HashMap<&str, String>,
+    ) -> Pin<Box<dyn Future<Output = anyhow::Result<String>> + Send + 'static>> {
+        unimplemented!()
+    }
+}
+
+#[tokio::test]
+async fn history_stats() {
+    let client = Client::from_transport(Arc::new(PagedTransport {
+        pages: vec![
+            vec![operation("5"), operation("4"), operation("3")],
+            vec![operation("2"), operation("1")],
+        ],
+    }));
+
+    let (stream, stats) =
+        client.operation_history_with_stats(Default::default(), None, None, None, 0, false, false);
+    let ops = stream.collect::<Vec<_>>().await;
+
+    assert_eq!(ops.len(), 5);
+    assert_eq!(stats.pages(), 2);
+    assert_eq!(stats.operations(), 5);
+    assert_eq!(stats.next_record(), None);
+}
+
+#[tokio::test]
+async fn history_estimate() {
+    let single = client(vec![operation("2"), operation("1")])
+        .estimate_history_pages(&Default::default(), None, DateRange::default())
+        .await
+        .unwrap();
+    assert_eq!(
+        single,
+        HistoryEstimate {
+            first_page_size: 2,
+            pages: Some(1)
+        }
+    );
+
+    let mut newest = operation("2");
+    newest["datetime"] = json!("2020-01-10T00:00:00Z");
+    let paged = Client::from_transport(Arc::new(PagedTransport {
+        pages: vec![vec![newest, operation("1")], vec![operation("0")]],
+    }))
+    .estimate_history_pages(
+        &Default::default(),
+        None,
+        DateRange {
+            from: Some("2019-12-13T00:00:00Z".parse().unwrap()),
+            till: None,
+        },
+    )
+    .await
+    .unwrap();
+    // First page spans 9 days out of 28
+    assert_eq!(paged.pages, Some(4));
+}
+
+/// Serves an unlabelled deposit from someone else, joined by the labelled one once `arrives_after`
+/// calls were made.
+#[derive(Debug)]
+struct DepositTransport {
+    calls: std::sync::atomic::AtomicUsize,
+    arrives_after: usize,
+}
+
+impl Transport for DepositTransport {
+    fn call(
+        &self,
+        _endpoint: &'static str,
+        params: &HashMap<&str, String>,
+    ) -> Pin<Box<dyn Future<Output = anyhow::Result<String>> + Send + 'static>> {
+        assert_eq!(params["types"], "deposition");
+        assert_eq!(params["label"], "order-1");
+        let calls = self.calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        let mut deposit = operation("1");
+        deposit["label"] = json!("order-1");
+        let unlabelled = operation("2");
+        let operations = if calls >= self.arrives_after {
+            vec![unlabelled, deposit]
+        } else {
+            vec![unlabelled]
+        };
+        let rsp = json!({ "operations": operations }).to_string();
+        Box::pin(async move { Ok(rsp) })
+    }
+
+    fn get_redirect(
+        &self,
+        _endpoint: &'static str,
+        _params: &HashMap<&str, String>,
+    ) -> Pin<Box<dyn Future<Output = anyhow::Result<String>> + Send + 'static>> {
+        unimplemented!()
+    }
+}
+
+#[tokio::test]
+async fn await_deposit() {
+    let transport = Arc::new(DepositTransport {
+        calls: Default::default(),
+        arrives_after: 2,
+    });
+    let client = Client::from_transport(transport.clone());
+    let op = client
+        .await_deposit(
+            "order-1",
+            std::time::Duration::from_secs(5),
+            std::time::Duration::from_millis(1),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(op.operation_id, "1");
+    assert_eq!(transport.calls.load(std::sync::atomic::Ordering::SeqCst), 3);
+
+    let client = Client::from_transport(Arc::new(DepositTransport {
+        calls: Default::default(),
+        arrives_after: usize::MAX,
+    }));
+    let op = client
+        .await_deposit(
+            "order-1",
+            std::time::Duration::from_millis(20),
+            std::time::Duration::from_millis(1),
+        )
+        .await
+        .unwrap();
+    assert!(op.is_none());
+}
+
+#[tokio::test]
+async fn single_history_page() {
+    let client = Client::from_transport(Arc::new(PagedTransport {
+        pages: vec![
+            vec![operation("5"), operation("4")],
+            vec![operation("3"), operation("2")],
+            vec![operation("1")],
+        ],
+    }));
+
+    let page = client
+        .operation_history_page(&HistoryFilter::default(), 1)
+        .await
+        .unwrap();
+    let ids = page
+        .operations
+        .iter()
+        .map(|op| op.operation_id.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(ids, ["3", "2"]);
+    assert_eq!(page.next_record.map(|n| n.0), Some(2));
+}
+
+#[tokio::test]
+async fn running_balance() {
+    let op = |id: &str, direction: &str, amount: &str, status: &str| {
+        let mut op = operation(id);
+        op["direction"] = json!(direction);
+        op["amount"] = json!(amount);
+        op["status"] = json!(status);
+        op
+    };
+    // Newest first, as served by the API
+    let client = client(vec![
+        op("4", "out", "30", "success"),
+        op("3", "out", "1000", "refused"),
+        op("2", "in", "50.5", "success"),
+        op("1", "out", "20", "success"),
+    ]);
+
+    let timeline = client
+        .operation_history(Default::default(), None, None, None, 0, false, false)
+        .with_running_balance(100.into(), true)
+        .map(|res| {
+            let (op, balance) = res.unwrap();
+            (op.operation_id, balance.to_string())
+        })
+        .collect::<Vec<_>>()
+        .await;
+    let expected = [("1", "80"), ("2", "130.5"), ("3", "130.5"), ("4", "100.5")]
+        .map(|(id, balance)| (id.to_string(), balance.to_string()));
+    assert_eq!(timeline, expected);
+}
+
+#[tokio::test]
+async fn history_as_ndjson() {
+    let client = Client::from_transport(Arc::new(PagedTransport {
+        pages: vec![vec![operation("3"), operation("2")], vec![operation("1")]],
+    }));
+
+    let mut out = Vec::new();
+    let written = client
+        .operation_history_to_writer(&HistoryFilter::default(), &mut out)
+        .await
+        .unwrap();
+    assert_eq!(written, 3);
+
+    let out = String::from_utf8(out).unwrap();
+    let ids = out
+        .lines()
+        .map(|line| {
+            serde_json::from_str::<Operation>(line)
+                .unwrap()
+                .operation_id
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(ids, ["3", "2", "1"]);
+    assert!(out.ends_with('\n'));
+}
+
+/// Serves operation details with the next of `statuses` on every call, repeating the last one.
+#[derive(Debug)]
+struct SettlingTransport {
+    statuses: Vec<&'static str>,
+    calls: std::sync::atomic::AtomicUsize,
+}
+
+impl Transport for SettlingTransport {
+    fn call(
+        &self,
+        endpoint: &'static str,
+        params: &HashMap<&str, String>,
+    ) -> Pin<Box<dyn Future<Output = anyhow::Result<String>> + Send + 'static>> {
+        assert_eq!(endpoint, "api/operation-details");
+        let call = self.calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        let rsp = json!({
+            "operation_id": params["operation_id"],
+            "status": self.statuses[call.min(self.statuses.len() - 1)],
+            "direction": "out",
+            "amount": "10",
+            "datetime": "2020-01-01T00:00:00Z",
+            "title": "Test",
+            "operation_type": "outgoing-transfer",
+        })
+        .to_string();
+        Box::pin(async move { Ok(rsp) })
+    }
+
+    fn get_redirect(
+        &self,
+        _endpoint: &'static str,
+        _params: &HashMap<&str, String>,
+    ) -> Pin<Box<dyn Future<Output = anyhow::Result<String>> + Send + 'static>> {
+        unimplemented!()
+    }
+}
+
+#[tokio::test]
+async fn details_settle() {
+    let settling = |statuses| {
+        Client::from_transport(Arc::new(SettlingTransport {
+            statuses,
+            calls: Default::default(),
+        }))
+    };
+    let interval = std::time::Duration::from_millis(1);
+
+    let details = settling(vec!["in_progress", "in_progress", "success"])
+        .operation_details_settled("1".into(), 5, interval)
+        .await
+        .unwrap();
+    assert!(matches!(details.status, OperationStatus::Success));
+
+    let details = settling(vec!["in_progress"])
+        .operation_details_settled("1".into(), 2, interval)
+        .await
+        .unwrap();
+    assert!(matches!(details.status, OperationStatus::InProgress));
+}
+
+#[tokio::test]
+async fn type_breakdown() {
+    let op = |id: &str, ty: &str, direction: &str, amount: &str, status: &str| {
+        let mut op = operation(id);
+        op["type"] = json!(ty);
+        op["direction"] = json!(direction);
+        op["amount"] = json!(amount);
+        op["status"] = json!(status);
+        op
+    };
+    let client = client(vec![
+        op("5", "deposition", "in", "100", "success"),
+        op("4", "deposition", "in", "50.5", "success"),
+        op("3", "payment-shop", "out", "30", "success"),
+        op("2", "outgoing-transfer", "out", "20", "success"),
+        op("1", "outgoing-transfer", "out", "1000", "refused"),
+    ]);
+
+    let breakdown = client
+        .operation_type_breakdown(DateRange::default())
+        .await
+        .unwrap();
+    let get = |ty| {
+        let (count, total): &(u64, BigDecimal) = &breakdown[&ty];
+        (*count, total.to_string())
+    };
+    assert_eq!(breakdown.len(), 3);
+    assert_eq!(get(RspOperationType::Deposition), (2, "150.5".into()));
+    assert_eq!(get(RspOperationType::PaymentShop), (1, "-30".into()));
+    assert_eq!(get(RspOperationType::OutgoingTransfer), (1, "-20".into()));
+}
+
+#[tokio::test]
+async fn resume_after_drop() {
+    let client = Client::from_transport(Arc::new(PagedTransport {
+        pages: vec![
+            vec![operation("5"), operation("4")],
+            vec![operation("3"), operation("2")],
+            vec![operation("1")],
+        ],
+    }));
+    let ids = |ops: Vec<anyhow::Result<Operation>>| {
+        ops.into_iter()
+            .map(|op| op.unwrap().operation_id)
+            .collect::<Vec<_>>()
+    };
+
+    let (stream, stats) =
+        client.operation_history_with_stats(Default::default(), None, None, None, 0, false, false);
+    assert_eq!(stats.resume_record(), Some(0));
+    // Shutting down in the middle of the second page
+    let taken = stream.take(3).collect::<Vec<_>>().await;
+    assert_eq!(ids(taken), ["5", "4", "3"]);
+    assert_eq!(stats.resume_record(), Some(1));
+
+    let (stream, stats) = client.operation_history_with_stats(
+        Default::default(),
+        None,
+        None,
+        None,
+        stats.resume_record().unwrap(),
+        false,
+        false,
+    );
+    assert_eq!(ids(stream.collect().await), ["3", "2", "1"]);
+    assert_eq!(stats.resume_record(), None);
+}
+
+#[tokio::test]
+async fn strict_mode_rejects_unknown_data() {
+    let details = |extra: serde_json::Value| {
+        let mut details = json!({
+            "operation_id": "1",
+            "status": "success",
+            "direction": "in",
+            "amount": "100.5",
+            "datetime": "2020-01-01T00:00:00Z",
+            "title": "Test",
+            "operation_type": "deposition",
+        });
+        details
+            .as_object_mut()
+            .unwrap()
+            .extend(extra.as_object().unwrap().clone());
+        Client::from_transport(Arc::new(MockTransport {
+            responses: [("api/operation-details", details)].into_iter().collect(),
+        }))
+    };
+
+    for extra in [
+        json!({ "cashback": "1.00" }),
+        json!({ "operation_type": "crypto-withdrawal" }),
+    ] {
+        let lenient = details(extra.clone());
+        assert!(lenient.operation_details("1".into()).await.is_ok());
+
+        let strict = details(extra).with_deserialize_mode(DeserializeMode::Strict);
+        let err = strict.operation_details("1".into()).await.unwrap_err();
+        assert!(
+            matches!(
+                err.downcast_ref::<Error>(),
+                Some(Error::UnexpectedResponse { details, .. }) if details.len() == 1
+            ),
+            "{err}"
+        );
+    }
+
+    let strict = details(json!({})).with_deserialize_mode(DeserializeMode::Strict);
+    assert!(strict.operation_details("1".into()).await.is_ok());
+}
+
+/// Serves two pages of history, rejecting every token other than `fresh`.
+#[derive(Debug)]
+struct RotatingTokenTransport {
+    bearer: std::sync::Mutex<String>,
+}
+
+impl Transport for RotatingTokenTransport {
+    fn call(
+        &self,
+        endpoint: &'static str,
+        params: &HashMap<&str, String>,
+    ) -> Pin<Box<dyn Future<Output = anyhow::Result<String>> + Send + 'static>> {
+        let rsp = if *self.bearer.lock().unwrap() != "fresh" {
+            Err(Error::InvalidToken { endpoint }.into())
+        } else if params["start-record"] == "0" {
+            Ok(json!({ "operations": [operation("2")], "next_record": "1" }).to_string())
+        } else {
+            Ok(json!({ "operations": [operation("1")] }).to_string())
+        };
+        Box::pin(async move { rsp })
+    }
+
+    fn get_redirect(
+        &self,
+        _endpoint: &'static str,
+        _params: &HashMap<&str, String>,
+    ) -> Pin<Box<dyn Future<Output = anyhow::Result<String>> + Send + 'static>> {
+        unimplemented!()
+    }
+
+    fn set_bearer(&self, bearer: String) -> anyhow::Result<()> {
+        *self.bearer.lock().unwrap() = bearer;
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn history_survives_token_rotation() {
+    let transport = || {
+        Arc::new(RotatingTokenTransport {
+            bearer: std::sync::Mutex::new("expired".into()),
+        })
+    };
+
+    let client = Client::from_transport(transport()).with_token_refresh(
+        "refresh".into(),
+        |token| async move {
+            assert_eq!(token, "refresh");
+            Ok(StoredCredentials::new("fresh".into()))
+        },
+    );
+    let ids = client
+        .operation_history(Default::default(), None, None, None, 0, false, false)
+        .map(|op| op.unwrap().operation_id)
+        .collect::<Vec<_>>()
+        .await;
+    assert_eq!(ids, ["2", "1"]);
+
+    let client = Client::from_transport(transport())
+        .with_token_refresh("refresh".into(), |_| async {
+            Err(anyhow::anyhow!("revoked"))
+        });
+    let res = client
+        .operation_history(Default::default(), None, None, None, 0, false, false)
+        .collect::<Vec<_>>()
+        .await;
+    assert_eq!(res.len(), 1);
+    assert!(matches!(
+        res[0].as_ref().unwrap_err().downcast_ref::<Error>(),
+        Some(Error::InvalidToken { .. })
+    ));
+}
@@ -0,0 +1,42 @@
+use std::time::Duration;
+use tokio::{io::AsyncWriteExt, net::TcpStream};
+use yoomoney::*;
+
+#[tokio::test]
+async fn port_in_use() {
+    let first = LoopbackServer::bind(0).await.unwrap();
+    let port = first.local_addr().unwrap().port();
+
+    assert!(matches!(
+        LoopbackServer::bind(port).await,
+        Err(Error::PortInUse { port: p }) if p == port
+    ));
+
+    // Port is released once the server is done
+    assert!(first
+        .capture_with_timeout(Duration::from_millis(10))
+        .await
+        .is_err());
+    LoopbackServer::bind(port).await.unwrap();
+}
+
+#[tokio::test]
+async fn captures_redirect() {
+    let server = LoopbackServer::bind(0).await.unwrap();
+    let addr = server.local_addr().unwrap();
+
+    tokio::spawn(async move {
+        let mut socket = TcpStream::connect(addr).await.unwrap();
+        socket
+            .write_all(b"GET /?code=abc&state=xyz HTTP/1.1\r\nhost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+    });
+
+    let response = server
+        .capture_with_timeout(Duration::from_secs(5))
+        .await
+        .unwrap();
+    assert_eq!(response.code, "abc");
+    assert_eq!(response.state.as_deref(), Some("xyz"));
+}
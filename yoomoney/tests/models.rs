@@ -0,0 +1,763 @@
+use bigdecimal::BigDecimal;
+use serde_json::json;
+use yoomoney::*;
+
+fn operation(fields: serde_json::Value) -> Operation {
+    let mut op = json!({
+        "operation_id": "1",
+        "status": "success",
+        "datetime": "2020-01-01T00:00:00Z",
+        "title": "Test",
+        "pattern_id": null,
+        "type": "deposition",
+    });
+    op.as_object_mut()
+        .unwrap()
+        .extend(fields.as_object().unwrap().clone());
+    serde_json::from_value(op).unwrap()
+}
+
+#[test]
+fn operation_amount_with_direction() {
+    let op = operation(json!({ "direction": "out", "amount": "10.50" }));
+    assert!(matches!(op.direction, TransferDirection::Out));
+    assert_eq!(op.amount, "10.50".parse::<BigDecimal>().unwrap());
+    assert_eq!(op.signed_amount(), "-10.50".parse::<BigDecimal>().unwrap());
+}
+
+#[test]
+fn operation_negative_amount_without_direction() {
+    let op = operation(json!({ "amount": "-3" }));
+    assert!(matches!(op.direction, TransferDirection::Out));
+    assert_eq!(op.amount, BigDecimal::from(3));
+    assert_eq!(op.signed_amount(), BigDecimal::from(-3));
+
+    let op = operation(json!({ "amount": "3" }));
+    assert!(matches!(op.direction, TransferDirection::In));
+    assert_eq!(op.signed_amount(), BigDecimal::from(3));
+}
+
+#[test]
+fn operation_protection() {
+    let op = operation(json!({
+        "amount": "5",
+        "type": "incoming-transfer-protected",
+        "codepro": true,
+        "protection_code_hint": "birthday",
+    }));
+    assert_eq!(op.codepro, Some(true));
+    assert_eq!(op.protection_code_hint.as_deref(), Some("birthday"));
+    assert!(op.is_protected());
+
+    // Not reported: judged by type
+    let op = operation(json!({ "amount": "5", "type": "incoming-transfer-protected" }));
+    assert_eq!(op.codepro, None);
+    assert!(op.is_protected());
+    assert!(!operation(json!({ "amount": "5" })).is_protected());
+
+    let op = operation(json!({ "amount": "5", "codepro": false }));
+    assert!(!op.is_protected());
+    let round_trip =
+        serde_json::from_value::<Operation>(serde_json::to_value(&op).unwrap()).unwrap();
+    assert_eq!(round_trip.codepro, Some(false));
+}
+
+#[test]
+fn fee_schedule_net_for_total() {
+    let fees = FeeSchedule::default();
+    for net in ["100", "0.01", "33.33", "199.99", "12345.67"] {
+        let net = net.parse::<BigDecimal>().unwrap();
+        let total = fees.total_for_net(&net, FeeSource::Wallet);
+        assert_eq!(
+            fees.net_for_total(&total, FeeSource::Wallet),
+            net,
+            "{total}"
+        );
+    }
+    // Fee rounding leaves a kopeck unused rather than overshooting
+    let one = BigDecimal::from(1);
+    let net = fees.net_for_total(&one, FeeSource::Card);
+    assert!(fees.total_for_net(&net, FeeSource::Card) <= one);
+}
+
+#[test]
+fn card_source_to_money_source() {
+    let sources = serde_json::from_value::<MoneySources>(json!({
+        "wallet": { "allowed": true },
+        "cards": {
+            "allowed": true,
+            "csc_required": true,
+            "items": [
+                { "id": "card-1", "pan_fragment": "510000******9999", "type": "MasterCard" },
+                { "id": "card-2", "pan_fragment": "427600******1234", "type": "VISA" },
+            ],
+        },
+    }))
+    .unwrap();
+    let cards = sources.cards.items.as_deref().unwrap();
+
+    for (card, id) in cards.iter().zip(["card-1", "card-2"]) {
+        for source in [
+            ProcessPaymentMoneySource::from_card_source(card),
+            ProcessPaymentMoneySource::from(card),
+        ] {
+            let ProcessPaymentMoneySource::Card {
+                id: source_id,
+                secure3d,
+                csc,
+            } = source
+            else {
+                panic!("expected card source")
+            };
+            assert_eq!(source_id, id);
+            assert!(secure3d.is_none() && csc.is_none());
+        }
+    }
+
+    let ProcessPaymentMoneySource::Card { id, .. } =
+        ProcessPaymentMoneySource::card_by_fragment(&sources, "1234").unwrap()
+    else {
+        panic!("expected card source")
+    };
+    assert_eq!(id, "card-2");
+}
+
+#[test]
+fn process_payment_next_retry() {
+    let now = "2020-01-01T00:00:00Z".parse().unwrap();
+    let rsp = ProcessPaymentResponse::InProgress { next_retry: 5000 };
+    assert_eq!(
+        rsp.next_retry_duration(),
+        Some(std::time::Duration::from_secs(5))
+    );
+    assert_eq!(
+        rsp.next_retry_after(now),
+        Some("2020-01-01T00:00:05Z".parse().unwrap())
+    );
+
+    let rsp = ProcessPaymentResponse::ExtAuthRequired(ExtAuth::default());
+    assert_eq!(rsp.next_retry_after(now), None);
+
+    // Accepted by the parser, but past the range of dates
+    let rsp = ProcessPaymentResponse::InProgress {
+        next_retry: 10_u64.pow(16),
+    };
+    assert!(rsp
+        .next_retry_duration()
+        .and_then(|d| chrono::Duration::from_std(d).ok())
+        .is_some());
+    assert_eq!(rsp.next_retry_after(now), None);
+}
+
+#[test]
+fn minimal_anonymous_account_info() {
+    let info = serde_json::from_value::<AccountInfo>(json!({
+        "account": "4100123456789",
+        "account_status": "anonymous",
+        "account_type": "personal",
+        "cards_linked": [],
+    }))
+    .unwrap();
+    assert_eq!(info.balance, BigDecimal::from(0));
+    assert_eq!(info.currency, "643");
+    assert!(matches!(info.account_status, AccountStatus::Anonymous));
+    assert!(info.balance_details.is_none());
+}
+
+#[test]
+fn account_info_round_trip() {
+    let wire = json!({
+        "account": "4100123456789",
+        "balance": 1000.34,
+        "currency": "643",
+        "account_status": "anonymous",
+        "account_type": "personal",
+        "balance_details": {
+            "total": 1000.34,
+            "available": 1000.34,
+            "deposition_pending": 0,
+            "blocked": 0,
+            "debt": 0,
+            "hold": 0,
+        },
+        "cards_linked": [{ "pan_fragment": "510000******9999", "type": "MasterCard" }],
+    });
+
+    let info = serde_json::from_value::<AccountInfo>(wire.clone()).unwrap();
+    assert_eq!(serde_json::to_value(&info).unwrap(), wire);
+}
+
+#[test]
+fn balance_breakdown() {
+    let details = serde_json::from_value::<BalanceDetails>(json!({
+        "total": 1000.0,
+        "available": 700.0,
+        "deposition_pending": 50.0,
+        "blocked": 100.0,
+        "debt": 25.5,
+        "hold": 174.5,
+    }))
+    .unwrap();
+
+    assert_eq!(details.unavailable(), BigDecimal::from(300));
+    assert_eq!(details.pending_in(), BigDecimal::from(50));
+    assert!(details.reconciles());
+
+    let details = BalanceDetails {
+        total: BigDecimal::from(1001),
+        ..details
+    };
+    assert!(!details.reconciles());
+}
+
+#[test]
+fn account_user_id() {
+    assert!(matches!(
+        UserId::account(410011161616877),
+        Ok(UserId::Account(410011161616877))
+    ));
+    assert!(matches!(
+        UserId::parse_account("4100 1116 1616 877"),
+        Ok(UserId::Account(410011161616877))
+    ));
+    assert!(matches!(
+        UserId::parse_account("4100-1116-1616-877"),
+        Ok(UserId::Account(410011161616877))
+    ));
+    // The prefix is kept, so a number typed without it is rejected
+    assert!(UserId::parse_account("11161616877").is_err());
+    assert!(UserId::parse_account("4100 abc").is_err());
+    assert!(UserId::account(0).is_err());
+    assert!(UserId::account(4100).is_err());
+    assert!(UserId::account(123456789012345).is_err());
+}
+
+fn account_info(balance: f64, status: &str, pans: &[&str]) -> AccountInfo {
+    serde_json::from_value(json!({
+        "account": "4100123456789",
+        "balance": balance,
+        "account_status": status,
+        "account_type": "personal",
+        "cards_linked": pans
+            .iter()
+            .map(|pan| json!({ "pan_fragment": pan, "type": "VISA" }))
+            .collect::<Vec<_>>(),
+    }))
+    .unwrap()
+}
+
+#[test]
+fn account_diff() {
+    let before = account_info(100.0, "anonymous", &["410000******1111"]);
+    assert!(before.diff(&before).is_empty());
+
+    let balance_increase = before.diff(&account_info(150.5, "anonymous", &["410000******1111"]));
+    assert_eq!(
+        balance_increase.balance_change,
+        "50.5".parse::<BigDecimal>().unwrap()
+    );
+    assert!(!balance_increase.is_empty());
+    assert!(balance_increase.cards_added.is_empty());
+
+    let card_added = before.diff(&account_info(
+        100.0,
+        "anonymous",
+        &["410000******1111", "510000******2222"],
+    ));
+    assert_eq!(card_added.cards_added.len(), 1);
+    assert_eq!(
+        card_added.cards_added[0].pan_fragment.as_deref(),
+        Some("510000******2222")
+    );
+    assert!(card_added.cards_removed.is_empty());
+
+    let upgrade = before.diff(&account_info(100.0, "identified", &["410000******1111"]));
+    assert_eq!(
+        upgrade.status_change,
+        Some((AccountStatus::Anonymous, AccountStatus::Identified))
+    );
+    assert!(upgrade.is_status_upgrade());
+}
+
+#[test]
+fn numbers_keep_precision() {
+    let info = serde_json::from_value::<AccountInfo>(json!({
+        "account": "41001234567890123456",
+        "balance": 1234567890123.45,
+        "account_status": "named",
+        "account_type": "personal",
+        "balance_details": {
+            "total": 1000.34,
+            "available": 0.1,
+            "deposition_pending": 0.2,
+            "blocked": 0,
+            "debt": "0.30",
+            "hold": 0,
+        },
+        "cards_linked": [],
+    }))
+    .unwrap();
+    assert_eq!(info.account, "41001234567890123456");
+    assert_eq!(info.balance.to_string(), "1234567890123.45");
+    let details = info.balance_details.unwrap();
+    assert_eq!(details.total.to_string(), "1000.34");
+    assert_eq!(
+        (&details.available + &details.deposition_pending).to_string(),
+        "0.3"
+    );
+    assert_eq!(details.debt, "0.3".parse::<BigDecimal>().unwrap());
+
+    let op = operation(json!({ "amount": 99999999999.99 }));
+    assert_eq!(op.amount.to_string(), "99999999999.99");
+    // Written back as a number, like the amounts of any other type
+    assert_eq!(
+        serde_json::to_value(&op).unwrap()["amount"],
+        json!(99999999999.99)
+    );
+}
+
+#[cfg(feature = "arbitrary-precision")]
+#[test]
+fn numbers_beyond_f64_keep_precision() {
+    // Read straight from the response text
+    let body = r#"{
+        "account": "4100123456789",
+        "balance": 123456789012345678.91,
+        "account_status": "named",
+        "account_type": "personal",
+        "cards_linked": []
+    }"#;
+    let info = serde_json::from_str::<AccountInfo>(body).unwrap();
+    assert_eq!(info.balance.to_string(), "123456789012345678.91");
+    let written = serde_json::to_string(&info).unwrap();
+    assert!(
+        written.contains(r#""balance":123456789012345678.91"#),
+        "{written}"
+    );
+
+    // Also when buffered by untagged and status-tagged enums
+    let rsp = serde_json::from_str::<Rsp<AccountInfo>>(body).unwrap();
+    assert_eq!(
+        rsp.into_result().unwrap().balance.to_string(),
+        "123456789012345678.91"
+    );
+    let rsp = serde_json::from_str::<RequestPaymentResponse>(
+        r#"{
+            "status": "success",
+            "balance": 0.1,
+            "request_id": "1",
+            "money_source": { "wallet": { "allowed": true }, "cards": { "allowed": false } },
+            "contract_amount": 99999999999999999999.99
+        }"#,
+    )
+    .unwrap();
+    let (_, data) = rsp.into_result().unwrap();
+    assert_eq!(
+        data.contract_amount.unwrap().to_string(),
+        "99999999999999999999.99"
+    );
+}
+
+#[test]
+fn authorization_denied_redirect() {
+    let err = AuthorizationResponse::from_redirect_url(
+        "https://example.com/cb?error=access_denied&error_description=User+refused&state=xyz",
+    )
+    .unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<Error>(),
+        Some(Error::AuthorizationDenied { reason }) if reason == "access_denied: User refused"
+    ));
+
+    let err =
+        AuthorizationResponse::from_redirect_url("https://example.com/cb?state=xyz").unwrap_err();
+    assert!(err.downcast_ref::<Error>().is_none());
+}
+
+#[test]
+fn account_bonus_balance() {
+    let without = account_info(10.0, "named", &[]);
+    assert_eq!(without.bonus_balance, None);
+    assert_eq!(without.bonus(), BigDecimal::from(0));
+    assert!(serde_json::to_value(&without)
+        .unwrap()
+        .get("bonus_balance")
+        .is_none());
+
+    let mut wire = serde_json::to_value(&without).unwrap();
+    wire["bonus_balance"] = json!(150.25);
+    let with = serde_json::from_value::<AccountInfo>(wire.clone()).unwrap();
+    assert_eq!(with.bonus(), "150.25".parse::<BigDecimal>().unwrap());
+    assert_eq!(serde_json::to_value(&with).unwrap(), wire);
+}
+
+#[test]
+fn credentials_export() {
+    let mut credentials = StoredCredentials::new("4100.it's-secret".into());
+    assert_eq!(
+        credentials.export(CredentialsFormat::Env).unwrap(),
+        "TOKEN='4100.it'\\''s-secret'\n"
+    );
+
+    credentials.scope = Some("account-info".into());
+    let toml = credentials.export(CredentialsFormat::Toml).unwrap();
+    assert!(toml.contains("access_token = \"4100.it's-secret\""));
+    let json = credentials.export(CredentialsFormat::Json).unwrap();
+    let value = serde_json::from_str::<serde_json::Value>(&json).unwrap();
+    assert_eq!(value["access_token"], "4100.it's-secret");
+    assert_eq!(value["scope"], "account-info");
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn credentials_file_is_private() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = std::env::temp_dir().join(format!("yoomoney-credentials-{}", std::process::id()));
+    let path = dir.join("credentials.toml");
+    // Permissions of a file that already exists are not kept either
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(&path, "").unwrap();
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+
+    let credentials = StoredCredentials::new("secret".into());
+    credentials.save(&path).await.unwrap();
+    let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+    let loaded = StoredCredentials::load(&path).await.unwrap();
+    assert_eq!(loaded.access_token, "secret");
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn request_payment_refusal_with_action() {
+    let blocked = serde_json::from_value::<RequestPaymentResponse>(json!({
+        "status": "refused",
+        "error": "account_blocked",
+        "account_unblock_uri": "https://yoomoney.ru/unblock",
+    }))
+    .unwrap();
+    assert!(matches!(
+        &blocked,
+        RequestPaymentResponse::Refused { error, account_unblock_uri: Some(_), .. } if error == "account_blocked"
+    ));
+    assert_eq!(blocked.action_uri(), Some("https://yoomoney.ru/unblock"));
+
+    let action = serde_json::from_value::<RequestPaymentResponse>(json!({
+        "status": "refused",
+        "error": "ext_action_required",
+        "ext_action_uri": "https://yoomoney.ru/action",
+    }))
+    .unwrap();
+    assert_eq!(action.action_uri(), Some("https://yoomoney.ru/action"));
+
+    let plain = serde_json::from_value::<RequestPaymentResponse>(json!({
+        "status": "refused",
+        "error": "not_enough_funds",
+    }))
+    .unwrap();
+    assert_eq!(plain.action_uri(), None);
+}
+
+#[test]
+fn request_payment_ext_action_required() {
+    let rsp = serde_json::from_value::<RequestPaymentResponse>(json!({
+        "status": "ext_action_required",
+        "ext_action_uri": "https://yoomoney.ru/confirm",
+    }))
+    .unwrap();
+    assert!(matches!(
+        rsp,
+        RequestPaymentResponse::ExtActionRequired { .. }
+    ));
+    assert_eq!(rsp.action_uri(), Some("https://yoomoney.ru/confirm"));
+    assert!(rsp.request_id().is_none());
+    assert_eq!(rsp.into_result().unwrap_err(), "ext_action_required");
+}
+
+#[test]
+fn amount_formatting() {
+    for (amount, formatted) in [
+        ("10", "10.00"),
+        ("10.5", "10.50"),
+        ("0.005", "0.01"),
+        ("-1.234", "-1.23"),
+        ("1000.34", "1000.34"),
+    ] {
+        assert_eq!(format_amount(&amount.parse().unwrap()), formatted);
+    }
+}
+
+fn operation_details(fields: serde_json::Value) -> OperationDetails {
+    let mut details = json!({
+        "operation_id": "1",
+        "status": "success",
+        "direction": "in",
+        "amount": "100.5",
+        "datetime": "2020-01-01T00:00:00Z",
+        "title": "Server title",
+        "operation_type": "deposition",
+    });
+    details
+        .as_object_mut()
+        .unwrap()
+        .extend(fields.as_object().unwrap().clone());
+    serde_json::from_value(details).unwrap()
+}
+
+#[test]
+fn operation_kind() {
+    let OperationKind::ShopPayment(shop) = operation_details(json!({
+        "direction": "out",
+        "operation_type": "payment-shop",
+        "pattern_id": "337",
+        "fee": "1.5",
+    }))
+    .kind()
+    .unwrap() else {
+        panic!("expected shop payment")
+    };
+    assert_eq!(shop.pattern_id.as_deref(), Some("337"));
+    assert_eq!(shop.fee, "1.5".parse::<BigDecimal>().unwrap());
+    assert_eq!(shop.amount_due, "100.5".parse::<BigDecimal>().unwrap());
+
+    let OperationKind::OutgoingTransfer(outgoing) = operation_details(json!({
+        "direction": "out",
+        "operation_type": "outgoing-transfer",
+        "recipient": "4100123456789",
+        "recipient_type": "account",
+        "amount_due": "101",
+        "protection_code": "1234",
+    }))
+    .kind()
+    .unwrap() else {
+        panic!("expected outgoing transfer")
+    };
+    assert_eq!(outgoing.recipient, "4100123456789");
+    assert!(matches!(
+        outgoing.recipient_type,
+        Some(RecipientType::Account)
+    ));
+    assert_eq!(outgoing.amount_due, BigDecimal::from(101));
+    assert_eq!(outgoing.fee, BigDecimal::from(0));
+    assert_eq!(outgoing.protection_code.as_deref(), Some("1234"));
+
+    let OperationKind::Deposition(deposit) = operation_details(json!({ "details": "Card top-up" }))
+        .kind()
+        .unwrap()
+    else {
+        panic!("expected deposition")
+    };
+    assert_eq!(deposit.details.as_deref(), Some("Card top-up"));
+
+    let OperationKind::IncomingTransfer(incoming) =
+        operation_details(json!({ "operation_type": "incoming-transfer", "message": "Thanks" }))
+            .kind()
+            .unwrap()
+    else {
+        panic!("expected incoming transfer")
+    };
+    assert_eq!(incoming.sender, None);
+    assert_eq!(incoming.message.as_deref(), Some("Thanks"));
+
+    let OperationKind::IncomingTransferProtected(protected) = operation_details(json!({
+        "operation_type": "incoming-transfer-protected",
+        "sender": "4100987654321",
+        "expires": "2020-01-02T00:00:00Z",
+    }))
+    .kind()
+    .unwrap() else {
+        panic!("expected protected transfer")
+    };
+    assert_eq!(protected.sender.as_deref(), Some("4100987654321"));
+    assert_eq!(
+        protected.expires,
+        Some("2020-01-02T00:00:00Z".parse().unwrap())
+    );
+
+    for fields in [
+        json!({ "operation_type": "outgoing-transfer" }),
+        json!({ "operation_type": "crypto-withdrawal" }),
+    ] {
+        assert!(matches!(
+            operation_details(fields).kind(),
+            Err(Error::IncompleteOperation { .. })
+        ));
+    }
+}
+
+#[test]
+fn operation_description() {
+    let deposit = operation_details(json!({}));
+    assert_eq!(deposit.describe(Locale::En), "Deposit of 100.50 RUB");
+    assert_eq!(deposit.describe(Locale::Ru), "Пополнение на 100.50 ₽");
+
+    let outgoing = operation_details(json!({
+        "direction": "out",
+        "operation_type": "outgoing-transfer",
+        "recipient": "4100123456789",
+    }));
+    assert_eq!(
+        outgoing.describe(Locale::En),
+        "Transfer of 100.50 RUB to 4100123456789"
+    );
+
+    let protected = operation_details(json!({
+        "operation_type": "incoming-transfer-protected",
+        "sender": "4100987654321",
+    }));
+    assert_eq!(
+        protected.describe(Locale::Ru),
+        "Перевод 100.50 ₽ от 4100987654321 с кодом протекции"
+    );
+
+    let anonymous = operation_details(json!({ "operation_type": "incoming-transfer" }));
+    assert_eq!(anonymous.describe(Locale::En), "Server title");
+
+    let shop = operation_details(json!({ "direction": "out", "operation_type": "payment-shop" }));
+    assert_eq!(shop.describe(Locale::En), "Server title");
+}
+
+#[test]
+fn details_money_fallbacks() {
+    let bare = operation_details(json!({}));
+    assert_eq!(bare.fee_or_zero(), BigDecimal::from(0));
+    assert_eq!(
+        bare.amount_due_or_amount(),
+        "100.5".parse::<BigDecimal>().unwrap()
+    );
+
+    let full = operation_details(json!({ "fee": "0.5", "amount_due": "101" }));
+    assert_eq!(full.fee_or_zero(), "0.5".parse::<BigDecimal>().unwrap());
+    assert_eq!(full.amount_due_or_amount(), BigDecimal::from(101));
+}
+
+#[test]
+fn nested_shop_params() {
+    use std::collections::BTreeMap;
+
+    let item = |name: &str, price: &str| {
+        ShopParam::Map(BTreeMap::from([
+            ("name".to_string(), name.into()),
+            ("price".to_string(), price.into()),
+        ]))
+    };
+    let params = flatten_shop_params(BTreeMap::from([
+        ("sum".to_string(), "30".into()),
+        (
+            "items".to_string(),
+            ShopParam::List(vec![item("tea", "10"), item("cake", "20")]),
+        ),
+        (
+            "tags".to_string(),
+            ShopParam::List(vec!["a".into(), "b".into()]),
+        ),
+    ]));
+
+    let expected = [
+        ("sum", "30"),
+        ("items[0][name]", "tea"),
+        ("items[0][price]", "10"),
+        ("items[1][name]", "cake"),
+        ("items[1][price]", "20"),
+        ("tags[0]", "a"),
+        ("tags[1]", "b"),
+    ]
+    .into_iter()
+    .map(|(k, v)| (k.to_string(), v.to_string()))
+    .collect::<std::collections::HashMap<_, _>>();
+    assert_eq!(params, expected);
+}
+
+#[test]
+fn ext_auth_actions() {
+    let action = |wire: serde_json::Value| match serde_json::from_value(wire).unwrap() {
+        ProcessPaymentResponse::ExtAuthRequired(ext_auth) => ext_auth.action(),
+        other => panic!("unexpected {other:?}"),
+    };
+
+    assert_eq!(
+        action(json!({
+            "status": "ext_auth_required",
+            "acs_uri": "https://acs.example.com/3ds",
+            "acs_params": { "MD": "1", "PaReq": "abc" },
+        })),
+        ExtAction::ThreeDSecure(AcsChallenge {
+            uri: "https://acs.example.com/3ds".into(),
+            params: json!({ "MD": "1", "PaReq": "abc" }),
+        })
+    );
+    assert_eq!(
+        action(json!({
+            "status": "ext_auth_required",
+            "ext_action_uri": "https://yoomoney.ru/confirm?sms=1",
+            "ext_action_type": "sms",
+        })),
+        ExtAction::Redirect("https://yoomoney.ru/confirm?sms=1".parse().unwrap())
+    );
+    assert_eq!(
+        action(json!({ "status": "ext_auth_required", "ext_action_type": "call" })),
+        ExtAction::Other(Some("call".into()))
+    );
+    assert_eq!(
+        action(json!({ "status": "ext_auth_required" })),
+        ExtAction::Other(None)
+    );
+}
+
+#[test]
+fn scope_string_round_trip() {
+    let granted = parse_scopes("account-info operation-history,payment-p2p").unwrap();
+    assert_eq!(
+        granted,
+        [
+            AccessScope::AccountInfo,
+            AccessScope::OperationHistory,
+            AccessScope::PaymentP2P
+        ]
+        .into_iter()
+        .collect()
+    );
+
+    let options = AuthorizeOptions::from(granted.clone());
+    assert_eq!(parse_scopes(&options.scope_param()).unwrap(), granted);
+
+    assert!(matches!(
+        parse_scopes("account-info money-source(\"wallet\")"),
+        Err(Error::UnknownScope { scope }) if scope == "money-source(\"wallet\")"
+    ));
+}
+
+#[test]
+fn operation_without_type() {
+    let mut op = json!({
+        "operation_id": "1",
+        "status": "success",
+        "datetime": "2020-01-01T00:00:00Z",
+        "title": "Legacy",
+        "pattern_id": null,
+        "direction": "in",
+        "amount": "5",
+    });
+    let parsed = serde_json::from_value::<Operation>(op.clone()).unwrap();
+    assert!(matches!(parsed.operation_type, RspOperationType::Unknown));
+    assert_eq!(parsed.title, "Legacy");
+    assert_eq!(parsed.amount, BigDecimal::from(5));
+
+    op["type"] = json!("some-future-type");
+    let parsed = serde_json::from_value::<Operation>(op).unwrap();
+    assert!(matches!(parsed.operation_type, RspOperationType::Unknown));
+
+    let details = serde_json::from_value::<OperationDetails>(json!({
+        "operation_id": "1",
+        "status": "success",
+        "direction": "in",
+        "amount": "5",
+        "datetime": "2020-01-01T00:00:00Z",
+        "title": "Legacy",
+    }))
+    .unwrap();
+    assert!(matches!(details.operation_type, RspOperationType::Unknown));
+    assert_eq!(details.describe(Locale::En), "Legacy");
+}
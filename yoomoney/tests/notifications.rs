@@ -0,0 +1,42 @@
+use yoomoney::*;
+
+const SECRET: &str = "01234567890ABCDEF01234567890";
+
+/// Example from the YooMoney documentation.
+fn notification() -> Notification {
+    serde_json::from_value(serde_json::json!({
+        "notification_type": "p2p-incoming",
+        "operation_id": "1234567",
+        "amount": "300.00",
+        "currency": "643",
+        "datetime": "2011-07-01T09:00:00.000+04:00",
+        "sender": "41001XXXXXXXX",
+        "codepro": "false",
+        "label": "",
+        "sha1_hash": "090a8e7ebb6982a7ad76f4c0f0fa5665d741aafa",
+    }))
+    .unwrap()
+}
+
+#[test]
+fn sha1_signature() {
+    let notification = notification();
+    assert_eq!(
+        notification.notification_type,
+        NotificationType::P2PIncoming
+    );
+    assert_eq!(
+        notification.sha1_signature(SECRET),
+        "090a8e7ebb6982a7ad76f4c0f0fa5665d741aafa"
+    );
+    assert_eq!(notification.verify(SECRET).unwrap(), SignatureScheme::Sha1);
+}
+
+#[test]
+fn wrong_secret() {
+    assert!(notification().verify("wrong").is_err());
+
+    let mut notification = notification();
+    notification.sha1_hash = String::new();
+    assert!(notification.verify(SECRET).is_err());
+}
@@ -0,0 +1,54 @@
+use std::collections::HashMap;
+use yoomoney::*;
+
+fn showcase() -> Showcase {
+    serde_json::from_value(serde_json::json!({
+        "title": "Test shop",
+        "form": [
+            { "name": "sum", "required": true, "min_amount": "10", "max_amount": "15000" },
+            { "name": "customerNumber", "required": true, "pattern": "[0-9]{10}" },
+            { "name": "comment" },
+        ],
+    }))
+    .unwrap()
+}
+
+fn params(sum: &str, customer: &str) -> HashMap<String, String> {
+    [("sum", sum), ("customerNumber", customer)]
+        .into_iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect()
+}
+
+#[test]
+fn valid_params() {
+    showcase().validate(&params("100", "0123456789")).unwrap();
+}
+
+#[test]
+fn amount_bounds() {
+    assert!(matches!(
+        showcase().validate(&params("9.99", "0123456789")),
+        Err(ShowcaseValidationError::BelowMin { field, .. }) if field == "sum"
+    ));
+    assert!(matches!(
+        showcase().validate(&params("15000.01", "0123456789")),
+        Err(ShowcaseValidationError::AboveMax { field, .. }) if field == "sum"
+    ));
+    assert!(matches!(
+        showcase().validate(&params("ten", "0123456789")),
+        Err(ShowcaseValidationError::NotANumber { field }) if field == "sum"
+    ));
+}
+
+#[test]
+fn pattern() {
+    assert!(matches!(
+        showcase().validate(&params("100", "01234567890")),
+        Err(ShowcaseValidationError::PatternMismatch { field, .. }) if field == "customerNumber"
+    ));
+    assert!(matches!(
+        showcase().validate(&HashMap::new()),
+        Err(ShowcaseValidationError::Missing { field }) if field == "sum"
+    ));
+}
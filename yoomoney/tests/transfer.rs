@@ -0,0 +1,451 @@
+use bigdecimal::BigDecimal;
+use parking_lot::Mutex;
+use serde_json::json;
+use std::{collections::HashMap, future::Future, pin::Pin, sync::Arc};
+use yoomoney::*;
+
+/// Transport recording request parameters and answering every call with an API error.
+#[derive(Debug, Default)]
+struct RecordingTransport {
+    params: Mutex<Vec<HashMap<String, String>>>,
+}
+
+impl Transport for RecordingTransport {
+    fn call(
+        &self,
+        _endpoint: &'static str,
+        params: &HashMap<&str, String>,
+    ) -> Pin<Box<dyn Future<Output = anyhow::Result<String>> + Send + 'static>> {
+        self.params.lock().push(
+            params
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.clone()))
+                .collect(),
+        );
+        let rsp = json!({ "error": "illegal_params" }).to_string();
+        Box::pin(async move { Ok(rsp) })
+    }
+
+    fn get_redirect(
+        &self,
+        _endpoint: &'static str,
+        _params: &HashMap<&str, String>,
+    ) -> Pin<Box<dyn Future<Output = anyhow::Result<String>> + Send + 'static>> {
+        unimplemented!()
+    }
+}
+
+fn transfer(client: &Client, comment: String, message: String) -> PaymentRequest {
+    client.request_transfer(
+        UserId::account(4_100_000_000_000_001).unwrap(),
+        RequestAmount::Net(BigDecimal::from(1)),
+        comment,
+        message,
+        None,
+        false,
+        None,
+        false,
+        0,
+    )
+}
+
+#[tokio::test]
+async fn transfer_text_at_limit_is_sent() {
+    let transport = Arc::new(RecordingTransport::default());
+    let client = Client::from_transport(transport.clone());
+
+    let _ = transfer(
+        &client,
+        "к".repeat(MAX_COMMENT_LEN),
+        "м".repeat(MAX_MESSAGE_LEN),
+    )
+    .send()
+    .await;
+
+    let params = transport.params.lock();
+    assert_eq!(params[0]["comment"].chars().count(), MAX_COMMENT_LEN);
+    assert_eq!(params[0]["message"].chars().count(), MAX_MESSAGE_LEN);
+}
+
+#[tokio::test]
+async fn over_long_transfer_text_is_rejected() {
+    let transport = Arc::new(RecordingTransport::default());
+    let client = Client::from_transport(transport.clone());
+
+    for (comment, message, expected) in [
+        (MAX_COMMENT_LEN + 1, 0, "comment"),
+        (0, MAX_MESSAGE_LEN + 1, "message"),
+    ] {
+        let err = transfer(&client, "a".repeat(comment), "b".repeat(message))
+            .send()
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<Error>(),
+            Some(Error::FieldTooLong { field, .. }) if *field == expected
+        ));
+    }
+
+    assert!(transport.params.lock().is_empty());
+}
+
+#[test]
+fn over_long_transfer_text_can_be_truncated() {
+    assert_eq!(
+        limit_length("comment", "abcd".into(), 3, true).unwrap(),
+        "abc"
+    );
+    assert!(limit_length("comment", "abcd".into(), 3, false).is_err());
+    assert_eq!(
+        limit_length("comment", "abc".into(), 3, false).unwrap(),
+        "abc"
+    );
+}
+
+#[test]
+fn protection_code_format() {
+    assert_eq!(
+        "0123".parse::<ProtectionCode>().unwrap().to_string(),
+        "0123"
+    );
+    for invalid in ["", "123", "12345", "12a4", "١٢٣٤", " 123"] {
+        assert!(invalid.parse::<ProtectionCode>().is_err(), "{invalid:?}");
+    }
+}
+
+#[tokio::test]
+async fn malformed_protection_code_is_not_sent() {
+    let transport = Arc::new(RecordingTransport::default());
+    let client = Client::from_transport(transport.clone());
+
+    assert!(client
+        .accept_incoming_transfer("1".into(), Some("12a4"))
+        .await
+        .is_err());
+    assert!(transport.params.lock().is_empty());
+
+    let _ = client
+        .accept_incoming_transfer("1".into(), Some("1234"))
+        .await;
+    assert_eq!(transport.params.lock()[0]["protection_code"], "1234");
+}
+
+#[tokio::test]
+async fn reserved_shop_params_are_rejected() {
+    let transport = Arc::new(RecordingTransport::default());
+    let client = Client::from_transport(transport.clone());
+
+    for key in RESERVED_SHOP_PARAMS {
+        let other = HashMap::from([(key.to_string(), "x".to_string())]);
+        let err = client
+            .request_shop_payment("pattern".into(), other)
+            .send()
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(err.downcast_ref::<Error>(), Some(Error::ReservedParam { key: k }) if k == key),
+            "{err:?}"
+        );
+    }
+    assert!(transport.params.lock().is_empty());
+
+    let other = HashMap::from([("sum".to_string(), "10".to_string())]);
+    let _ = client
+        .request_shop_payment("pattern".into(), other)
+        .send()
+        .await;
+    let sent = transport.params.lock().pop().unwrap();
+    assert_eq!(sent["pattern_id"], "pattern");
+    assert_eq!(sent["sum"], "10");
+}
+
+#[tokio::test]
+async fn test_payment_flag_is_processed() {
+    let transport = Arc::new(RecordingTransport::default());
+    let client = Client::from_transport(transport.clone());
+
+    let _ = client
+        .process_test_payment(
+            "request".into(),
+            ProcessPaymentMoneySource::Wallet,
+            &TestPayment {
+                card: Some(TestCard::Available),
+                result: Some(TestResult::Other("not_enough_funds".into())),
+            },
+        )
+        .await;
+    let sent = transport.params.lock().pop().unwrap();
+    assert_eq!(sent["test_payment"], "true");
+    assert_eq!(sent["test_card"], "available");
+    assert_eq!(sent["test_result"], "not_enough_funds");
+    assert_eq!(sent["request_id"], "request");
+
+    let _ = client
+        .process_payment("request".into(), ProcessPaymentMoneySource::Wallet)
+        .await;
+    let sent = transport.params.lock().pop().unwrap();
+    assert!(!sent.contains_key("test_payment"));
+}
+
+/// Refuses every protection code, counting attempts down from `attempts`.
+#[derive(Debug)]
+struct WrongCodeTransport {
+    attempts: u32,
+    calls: Mutex<u32>,
+}
+
+impl Transport for WrongCodeTransport {
+    fn call(
+        &self,
+        endpoint: &'static str,
+        _params: &HashMap<&str, String>,
+    ) -> Pin<Box<dyn Future<Output = anyhow::Result<String>> + Send + 'static>> {
+        assert_eq!(endpoint, "api/incoming-transfer-accept");
+        let mut calls = self.calls.lock();
+        *calls += 1;
+        let rsp = json!({
+            "status": "refused",
+            "error": "illegal_param_protection_code",
+            "protection_code_attempts_available": self.attempts.saturating_sub(*calls),
+        })
+        .to_string();
+        Box::pin(async move { Ok(rsp) })
+    }
+
+    fn get_redirect(
+        &self,
+        _endpoint: &'static str,
+        _params: &HashMap<&str, String>,
+    ) -> Pin<Box<dyn Future<Output = anyhow::Result<String>> + Send + 'static>> {
+        unimplemented!()
+    }
+}
+
+#[tokio::test]
+async fn protection_code_attempts_run_out() {
+    let transport = Arc::new(WrongCodeTransport {
+        attempts: 3,
+        calls: Mutex::new(0),
+    });
+    let client = Client::from_transport(transport.clone());
+    let mut attempts = ProtectionCodeAttempts::new("1".into());
+
+    for left in [2, 1, 0] {
+        let rsp = client
+            .accept_protected_transfer(&mut attempts, "0000")
+            .await
+            .unwrap();
+        assert_eq!(rsp.attempts_available(), Some(left));
+        assert_eq!(attempts.available, Some(left));
+    }
+    assert!(attempts.is_exhausted());
+
+    let err = client
+        .accept_protected_transfer(&mut attempts, "1234")
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<Error>(),
+        Some(Error::AttemptsExhausted { operation_id }) if operation_id == "1"
+    ));
+    assert_eq!(*transport.calls.lock(), 3);
+}
+
+#[tokio::test]
+async fn expired_request_is_not_processed() {
+    let transport = Arc::new(RecordingTransport::default());
+    let client = Client::from_transport(transport.clone());
+    let request = |valid_until: chrono::DateTime<chrono::Utc>| {
+        serde_json::from_value::<RequestPaymentSuccessData>(json!({
+            "balance": "100",
+            "request_id": "request",
+            "money_source": {
+                "wallet": { "allowed": true },
+                "cards": { "allowed": false },
+            },
+            "request_valid_until": valid_until,
+        }))
+        .unwrap()
+    };
+    let now = chrono::Utc::now();
+
+    let expired = request(now - chrono::Duration::minutes(1));
+    assert!(expired.is_request_expired());
+    let err = client
+        .process_requested_payment(&expired, ProcessPaymentMoneySource::Wallet)
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<Error>(),
+        Some(Error::RequestExpired { request_id, .. }) if request_id == "request"
+    ));
+    assert!(transport.params.lock().is_empty());
+
+    let fresh = request(now + chrono::Duration::minutes(10));
+    assert!(!fresh.is_request_expired());
+    let _ = client
+        .process_requested_payment(&fresh, ProcessPaymentMoneySource::Wallet)
+        .await;
+    assert_eq!(
+        transport.params.lock().pop().unwrap()["request_id"],
+        "request"
+    );
+}
+
+#[tokio::test]
+async fn csc_is_sent_with_card() {
+    let transport = Arc::new(RecordingTransport::default());
+    let client = Client::from_transport(transport.clone());
+
+    let _ = client
+        .process_payment(
+            "request".into(),
+            ProcessPaymentMoneySource::Card {
+                id: "card-1".into(),
+                secure3d: None,
+                csc: Some("123".into()),
+            },
+        )
+        .await;
+    let sent = transport.params.lock().pop().unwrap();
+    assert_eq!(sent["money_source"], "card-1");
+    assert_eq!(sent["csc"], "123");
+}
+
+#[tokio::test]
+async fn invalid_csc_is_not_sent() {
+    let transport = Arc::new(RecordingTransport::default());
+    let client = Client::from_transport(transport.clone());
+
+    for csc in ["12", "12345", "12a"] {
+        let source = ProcessPaymentMoneySource::Card {
+            id: "card-1".into(),
+            secure3d: None,
+            csc: Some(csc.into()),
+        };
+        assert!(source.validate().is_err(), "{csc}");
+        assert!(client
+            .process_payment("request".into(), source)
+            .await
+            .is_err());
+    }
+    assert!(transport.params.lock().is_empty());
+
+    for csc in ["123", "1234"] {
+        let source = ProcessPaymentMoneySource::Card {
+            id: "card-1".into(),
+            secure3d: None,
+            csc: Some(csc.into()),
+        };
+        assert!(source.validate().is_ok(), "{csc}");
+    }
+}
+
+#[tokio::test]
+async fn recipient_params_per_user_id() {
+    let transport = Arc::new(RecordingTransport::default());
+    let client = Client::from_transport(transport.clone());
+
+    for (to, expected_to, expected_type) in [
+        (
+            UserId::account(4_100_123_456_789).unwrap(),
+            "4100123456789",
+            "account",
+        ),
+        (
+            UserId::Phone(phonenumber::parse(None, "+79001234567").unwrap()),
+            "+79001234567",
+            "phone",
+        ),
+        (
+            UserId::Email("user@example.com".into()),
+            "user@example.com",
+            "email",
+        ),
+    ] {
+        let _ = client
+            .request_transfer(
+                to,
+                RequestAmount::Net(BigDecimal::from(1)),
+                String::new(),
+                String::new(),
+                None,
+                false,
+                None,
+                false,
+                0,
+            )
+            .send()
+            .await;
+        let sent = transport.params.lock().pop().unwrap();
+        assert_eq!(sent["to"], expected_to);
+        assert_eq!(sent["identifier_type"], expected_type);
+    }
+}
+
+/// Fails with each of `errors` in turn, then refuses the payment.
+#[derive(Debug, Default)]
+struct FlakyTransport {
+    errors: Mutex<Vec<Error>>,
+    calls: Mutex<u32>,
+}
+
+impl Transport for FlakyTransport {
+    fn call(
+        &self,
+        _endpoint: &'static str,
+        _params: &HashMap<&str, String>,
+    ) -> Pin<Box<dyn Future<Output = anyhow::Result<String>> + Send + 'static>> {
+        *self.calls.lock() += 1;
+        let mut errors = self.errors.lock();
+        let res = if errors.is_empty() {
+            Ok(json!({ "status": "refused", "error": "not_enough_funds" }).to_string())
+        } else {
+            Err(errors.remove(0).into())
+        };
+        Box::pin(async move { res })
+    }
+
+    fn get_redirect(
+        &self,
+        _endpoint: &'static str,
+        _params: &HashMap<&str, String>,
+    ) -> Pin<Box<dyn Future<Output = anyhow::Result<String>> + Send + 'static>> {
+        unimplemented!()
+    }
+}
+
+#[tokio::test]
+async fn safe_payment_polls_only_on_unknown_outcome() {
+    let transport = Arc::new(FlakyTransport {
+        errors: Mutex::new(vec![Error::Maintenance { status: 503 }]),
+        ..Default::default()
+    });
+    let client = Client::from_transport(transport.clone());
+    let err = client
+        .process_payment_safe("request".into(), ProcessPaymentMoneySource::Wallet, 3)
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("not_enough_funds"), "{err:?}");
+    assert_eq!(*transport.calls.lock(), 2);
+
+    for error in [
+        Error::InvalidToken {
+            endpoint: "process-payment",
+        },
+        Error::ReservedParam {
+            key: "request_id".into(),
+        },
+    ] {
+        let transport = Arc::new(FlakyTransport {
+            errors: Mutex::new(vec![error]),
+            ..Default::default()
+        });
+        let client = Client::from_transport(transport.clone());
+        client
+            .process_payment_safe("request".into(), ProcessPaymentMoneySource::Wallet, 3)
+            .await
+            .unwrap_err();
+        assert_eq!(*transport.calls.lock(), 1);
+    }
+}
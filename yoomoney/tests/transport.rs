@@ -0,0 +1,622 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+};
+use yoomoney::*;
+
+/// Serve a single raw HTTP response, returning the server address.
+async fn serve_raw(response: String) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        let (mut socket, _) = listener.accept().await.unwrap();
+        let mut buf = [0; 4096];
+        let _ = socket.read(&mut buf).await;
+        let _ = socket.write_all(response.as_bytes()).await;
+    });
+
+    format!("http://{addr}")
+}
+
+/// Serve a single raw HTTP response, handing the received request over to the returned receiver.
+async fn serve_capturing(response: String) -> (String, tokio::sync::oneshot::Receiver<String>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (tx, rx) = tokio::sync::oneshot::channel();
+    tokio::spawn(async move {
+        let (mut socket, _) = listener.accept().await.unwrap();
+        // Headers and body may arrive separately, so read until the announced body is complete
+        let mut request = Vec::new();
+        let mut buf = [0; 4096];
+        loop {
+            let n = socket.read(&mut buf).await.unwrap();
+            request.extend_from_slice(&buf[..n]);
+            let text = String::from_utf8_lossy(&request);
+            let complete = text.split_once("\r\n\r\n").is_some_and(|(head, body)| {
+                let len = head
+                    .lines()
+                    .find_map(|l| {
+                        l.to_lowercase()
+                            .strip_prefix("content-length: ")
+                            .map(str::to_string)
+                    })
+                    .and_then(|len| len.parse().ok())
+                    .unwrap_or(0);
+                body.len() >= len
+            });
+            if n == 0 || complete {
+                break;
+            }
+        }
+        let _ = tx.send(String::from_utf8_lossy(&request).into_owned());
+        let _ = socket.write_all(response.as_bytes()).await;
+    });
+
+    (format!("http://{addr}"), rx)
+}
+
+/// Serve raw HTTP responses in order, one per connection.
+async fn serve_sequence(responses: Vec<String>) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        for response in responses {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut buf = [0; 4096];
+            let _ = socket.read(&mut buf).await;
+            let _ = socket.write_all(response.as_bytes()).await;
+        }
+    });
+
+    format!("http://{addr}")
+}
+
+/// Serve a single HTTP response with the given body, returning the server address.
+async fn serve_once(body: String) -> String {
+    serve_raw(format!(
+        "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\n\r\n{}",
+        body.len(),
+        body
+    ))
+    .await
+}
+
+#[tokio::test]
+async fn oversized_response_is_rejected() {
+    let addr = serve_once(format!("{{\"error\": \"{}\"}}", "x".repeat(1024))).await;
+    let client = Client::builder()
+        .addr(addr)
+        .token("token")
+        .max_response_bytes(512)
+        .build();
+
+    let err = client.account_info().await.unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<Error>(),
+        Some(Error::ResponseTooLarge { limit: 512 })
+    ));
+}
+
+#[derive(Clone, Default)]
+struct LogBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl std::io::Write for LogBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn retries_are_logged() {
+    let logs = LogBuffer::default();
+    let subscriber = tracing_subscriber::fmt()
+        .with_writer({
+            let logs = logs.clone();
+            move || logs.clone()
+        })
+        .with_ansi(false)
+        .with_max_level(tracing::Level::DEBUG)
+        .finish();
+    let _guard = tracing::subscriber::set_default(subscriber);
+
+    // Nothing listens on a port that was just released
+    let addr = TcpListener::bind("127.0.0.1:0")
+        .await
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let client = Client::builder()
+        .addr(format!("http://{addr}"))
+        .token("token")
+        .retry_policy(RetryPolicy {
+            max_retries: 2,
+            base_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(10),
+        })
+        .build();
+
+    assert!(client.account_info().await.is_err());
+
+    let logs = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+    assert!(logs.contains("attempt=1"), "{logs}");
+    assert!(logs.contains("attempt=2"), "{logs}");
+    assert!(!logs.contains("attempt=3"), "{logs}");
+    assert!(logs.contains("endpoint=\"api/account-info\""), "{logs}");
+}
+
+#[tokio::test]
+async fn tokenless_client_is_not_authorized() {
+    let client = Client::new(None::<String>);
+
+    let err = client.account_info().await.unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<Error>(),
+        Some(Error::NotAuthorized {
+            endpoint: "api/account-info"
+        })
+    ));
+}
+
+/// Respond to every request with account info whose account number is the bearer token.
+async fn serve_echo_bearer() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        loop {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            tokio::spawn(async move {
+                let mut buf = vec![0; 8192];
+                let n = socket.read(&mut buf).await.unwrap();
+                let req = String::from_utf8_lossy(&buf[..n]);
+                let token = req
+                    .lines()
+                    .find_map(|l| l.strip_prefix("authorization: Bearer "))
+                    .unwrap_or_default()
+                    .trim()
+                    .to_string();
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                let body = serde_json::json!({
+                    "account": token,
+                    "account_status": "named",
+                    "account_type": "personal",
+                    "cards_linked": [],
+                })
+                .to_string();
+                let _ = socket
+                    .write_all(
+                        format!(
+                            "HTTP/1.1 200 OK\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                            body.len(),
+                            body
+                        )
+                        .as_bytes(),
+                    )
+                    .await;
+            });
+        }
+    });
+
+    format!("http://{addr}")
+}
+
+#[tokio::test]
+async fn bearer_override_does_not_leak() {
+    let client = Client::builder()
+        .addr(serve_echo_bearer().await)
+        .token("default")
+        .build();
+
+    let mut tasks = tokio::task::JoinSet::new();
+    for i in 0..10 {
+        let client = client.with_bearer(&format!("user{i}")).unwrap();
+        tasks.spawn(async move { (i, client.account_info().await.unwrap().account) });
+    }
+    while let Some(res) = tasks.join_next().await {
+        let (i, account) = res.unwrap();
+        assert_eq!(account, format!("user{i}"));
+    }
+
+    assert_eq!(client.account_info().await.unwrap().account, "default");
+}
+
+#[test]
+fn sandbox_detection() {
+    let production = ClientBuilder::new().build();
+    assert_eq!(production.base_url().as_deref(), Some(DEFAULT_ADDR));
+    assert!(!production.is_sandbox());
+
+    let sandbox = ClientBuilder::new().sandbox().build();
+    assert_eq!(sandbox.base_url().as_deref(), Some(SANDBOX_ADDR));
+    assert!(sandbox.is_sandbox());
+
+    let by_addr = ClientBuilder::new()
+        .addr(format!("{SANDBOX_ADDR}/"))
+        .build();
+    assert!(by_addr.is_sandbox());
+}
+
+async fn authorize_against(addr: String) -> anyhow::Error {
+    ClientBuilder::new()
+        .addr(addr)
+        .build_unauthorized("client".into(), "https://example.com".into())
+        .authorize(Default::default(), |_| async {
+            Ok::<_, anyhow::Error>(AuthorizationResponse {
+                code: "code".into(),
+                state: None,
+            })
+        })
+        .await
+        .unwrap_err()
+}
+
+#[tokio::test]
+async fn authorize_without_redirect_is_an_error() {
+    let addr = serve_once("{}".into()).await;
+    let err = authorize_against(addr).await;
+    assert!(matches!(
+        err.downcast_ref::<Error>(),
+        Some(Error::RedirectMissing { status: 200 })
+    ));
+}
+
+#[tokio::test]
+async fn redirect_without_location_is_an_error() {
+    let addr = serve_raw("HTTP/1.1 302 Found\r\ncontent-length: 0\r\n\r\n".into()).await;
+    let err = authorize_against(addr).await;
+    assert!(matches!(
+        err.downcast_ref::<Error>(),
+        Some(Error::RedirectMissing { status: 302 })
+    ));
+}
+
+#[tokio::test]
+async fn language_header_is_sent() {
+    let body = r#"{"error":"illegal_params"}"#;
+    let (addr, request) = serve_capturing(format!(
+        "HTTP/1.1 200 OK\r\ncontent-length: {}\r\n\r\n{body}",
+        body.len()
+    ))
+    .await;
+    let client = Client::builder()
+        .addr(addr)
+        .token("token")
+        .language("en")
+        .build();
+    let _ = client.account_info().await;
+    let request = request.await.unwrap().to_lowercase();
+    assert!(request.contains("accept-language: en\r\n"), "{request}");
+
+    let (addr, request) =
+        serve_capturing("HTTP/1.1 302 Found\r\ncontent-length: 0\r\n\r\n".into()).await;
+    let _ = authorize_against(addr).await;
+    let request = request.await.unwrap().to_lowercase();
+    assert!(
+        request.contains(&format!("accept-language: {DEFAULT_LANGUAGE}\r\n")),
+        "{request}"
+    );
+}
+
+fn maintenance_page(status: &str) -> String {
+    let body = "<html><body>Technical works</body></html>";
+    format!(
+        "HTTP/1.1 {status}\r\ncontent-type: text/html\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{body}",
+        body.len()
+    )
+}
+
+#[tokio::test]
+async fn maintenance_page_is_detected() {
+    let addr = serve_once("<!DOCTYPE html><html></html>".into()).await;
+    let client = Client::builder().addr(addr).token("token").build();
+    let err = client.account_info().await.unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<Error>(),
+        Some(Error::Maintenance { status: 200 })
+    ));
+
+    let body = serde_json::json!({
+        "account": "4100123",
+        "account_status": "named",
+        "account_type": "personal",
+        "cards_linked": [],
+    })
+    .to_string();
+    let addr = serve_sequence(vec![
+        maintenance_page("502 Bad Gateway"),
+        format!(
+            "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\n\r\n{body}",
+            body.len()
+        ),
+    ])
+    .await;
+    let client = Client::builder()
+        .addr(addr)
+        .token("token")
+        .retry_policy(RetryPolicy {
+            max_retries: 1,
+            base_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(1),
+        })
+        .build();
+    assert_eq!(client.account_info().await.unwrap().account, "4100123");
+}
+
+#[tokio::test]
+async fn unauthorized_html_page_is_invalid_token() {
+    // Served once only: a retry would find nothing listening
+    let addr = serve_raw(maintenance_page("401 Unauthorized")).await;
+    let client = Client::builder()
+        .addr(addr)
+        .token("revoked")
+        .retry_policy(RetryPolicy {
+            max_retries: 1,
+            base_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(1),
+        })
+        .build();
+    let err = client.account_info().await.unwrap_err();
+    assert!(
+        matches!(
+            err.downcast_ref::<Error>(),
+            Some(Error::InvalidToken { .. })
+        ),
+        "{err:?}"
+    );
+
+    let addr = serve_raw(maintenance_page("401 Unauthorized")).await;
+    let client = Client::builder().addr(addr).token("revoked").build();
+    client.revoke_token().await.unwrap();
+}
+
+#[tokio::test]
+async fn not_found_html_page_is_not_maintenance() {
+    // Served once only: a retry would find nothing listening
+    let addr = serve_raw(maintenance_page("404 Not Found")).await;
+    let client = Client::builder()
+        .addr(addr)
+        .token("token")
+        .retry_policy(RetryPolicy {
+            max_retries: 1,
+            base_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(1),
+        })
+        .build();
+    let err = client.account_info().await.unwrap_err();
+    assert!(err.downcast_ref::<Error>().is_none(), "{err:?}");
+    assert!(err.to_string().contains("404"), "{err:?}");
+}
+
+#[tokio::test]
+async fn revoking_revoked_token_succeeds() {
+    let addr = serve_raw(
+        "HTTP/1.1 401 Unauthorized\r\nwww-authenticate: Bearer error=\"invalid_token\"\r\ncontent-length: 0\r\n\r\n"
+            .into(),
+    )
+    .await;
+    let client = Client::builder().addr(addr).token("revoked").build();
+    client.revoke_token().await.unwrap();
+
+    // Nothing listens on a port that was just released
+    let addr = TcpListener::bind("127.0.0.1:0")
+        .await
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let client = Client::builder()
+        .addr(format!("http://{addr}"))
+        .token("token")
+        .build();
+    let err = client.revoke_token().await.unwrap_err();
+    assert!(err.downcast_ref::<reqwest::Error>().is_some(), "{err:?}");
+}
+
+#[test]
+fn builder_settings_are_reflected_in_config() {
+    let retry_policy = RetryPolicy {
+        max_retries: 3,
+        base_delay: Duration::from_millis(10),
+        max_delay: Duration::from_secs(1),
+    };
+    let client = Client::builder()
+        .addr("http://localhost:1234")
+        .retry_policy(retry_policy)
+        .request_timeout(Duration::from_secs(30))
+        .connect_timeout(Duration::from_secs(5))
+        .max_concurrent_requests(4)
+        .details_concurrency(8)
+        .language("en")
+        .build();
+
+    let config = client.config();
+    assert_eq!(config.base_url.as_deref(), Some("http://localhost:1234"));
+    assert_eq!(config.retry_policy.max_retries, 3);
+    assert_eq!(config.retry_policy.base_delay, Duration::from_millis(10));
+    assert_eq!(config.request_timeout, Some(Duration::from_secs(30)));
+    assert_eq!(config.connect_timeout, Some(Duration::from_secs(5)));
+    assert_eq!(config.max_concurrent_requests, Some(4));
+    assert_eq!(config.details_concurrency, 8);
+    assert_eq!(config.language, "en");
+    assert!(!config.sandbox);
+
+    let defaults = Client::builder().build();
+    assert_eq!(
+        defaults.config().max_response_bytes,
+        DEFAULT_MAX_RESPONSE_BYTES
+    );
+    assert_eq!(
+        defaults.config().details_concurrency,
+        DEFAULT_DETAILS_CONCURRENCY
+    );
+    assert_eq!(defaults.config().request_timeout, None);
+}
+
+#[test]
+fn hmac_signing_vectors() {
+    let params = std::collections::HashMap::from([
+        ("to", "4100123".to_string()),
+        ("pattern_id", "p2p".to_string()),
+        ("amount", "10.00".to_string()),
+    ]);
+    assert_eq!(
+        HmacSha256Signer::canonical_string("api/request-payment", &params),
+        "api/request-payment\namount=10.00&pattern_id=p2p&to=4100123"
+    );
+    assert_eq!(
+        HmacSha256Signer::new("secret").signature("api/request-payment", &params),
+        "7addd94766319f842bdd92febfd56892355c8a2ac884348625aee16d74bff8bf"
+    );
+    assert_eq!(
+        HmacSha256Signer::new("Jefe").signature("api/account-info", &Default::default()),
+        "3832b233624dfcf664d4feb490371e9eb34a074ca967dbb69c3c5aecec90c4d9"
+    );
+    assert!(NoopSigner
+        .sign("api/account-info", &params)
+        .headers
+        .is_empty());
+}
+
+#[tokio::test]
+async fn signature_header_is_sent() {
+    let body = r#"{"error":"illegal_params"}"#;
+    let (addr, request) = serve_capturing(format!(
+        "HTTP/1.1 200 OK\r\ncontent-length: {}\r\n\r\n{body}",
+        body.len()
+    ))
+    .await;
+    let client = Client::builder()
+        .addr(addr)
+        .token("token")
+        .request_signer(HmacSha256Signer::new("Jefe"))
+        .build();
+    let _ = client.account_info().await;
+
+    let request = request.await.unwrap();
+    assert!(
+        request.contains(
+            "x-signature: 3832b233624dfcf664d4feb490371e9eb34a074ca967dbb69c3c5aecec90c4d9\r\n"
+        ),
+        "{request}"
+    );
+}
+
+#[test]
+fn client_from_env() {
+    let env = |vars: &'static [(&'static str, &'static str)]| {
+        move |var: &str| {
+            vars.iter()
+                .find(|(k, _)| *k == var)
+                .map(|(_, v)| v.to_string())
+        }
+    };
+
+    let client = Client::from_env_with(env(&[
+        ("YOOMONEY_TOKEN", "token"),
+        ("YOOMONEY_BASE_URL", "http://localhost:1234"),
+        ("YOOMONEY_REQUEST_TIMEOUT_SECS", "30"),
+        ("YOOMONEY_MAX_RETRIES", "2"),
+        ("YOOMONEY_MAX_CONCURRENT_REQUESTS", "4"),
+        ("YOOMONEY_LANGUAGE", "en"),
+    ]))
+    .unwrap();
+    let config = client.config();
+    assert_eq!(config.base_url.as_deref(), Some("http://localhost:1234"));
+    assert_eq!(config.request_timeout, Some(Duration::from_secs(30)));
+    assert_eq!(config.connect_timeout, None);
+    assert_eq!(config.retry_policy.max_retries, 2);
+    assert_eq!(config.max_concurrent_requests, Some(4));
+    assert_eq!(config.language, "en");
+
+    let err = Client::from_env_with(env(&[("YOOMONEY_BASE_URL", "http://localhost")])).err();
+    assert!(matches!(err, Some(Error::MissingEnv { vars }) if vars == [ENV_TOKEN]));
+
+    let err = Client::from_env_with(env(&[
+        ("YOOMONEY_TOKEN", "token"),
+        ("YOOMONEY_MAX_RETRIES", "many"),
+    ]))
+    .err();
+    assert!(matches!(
+        err,
+        Some(Error::InvalidEnv { var: "YOOMONEY_MAX_RETRIES", value }) if value == "many"
+    ));
+
+    // Only this test touches these variables
+    std::env::set_var(ENV_TOKEN, "from-env");
+    std::env::set_var("YOOMONEY_LANGUAGE", "en");
+    let client = Client::from_env().unwrap();
+    assert_eq!(client.config().language, "en");
+    assert_eq!(client.config().base_url.as_deref(), Some(DEFAULT_ADDR));
+}
+
+#[tokio::test]
+async fn body_encoding() {
+    for (encoding, override_encoding, content_type, body) in [
+        (
+            BodyEncoding::Form,
+            None,
+            "application/x-www-form-urlencoded",
+            "operation_id=1",
+        ),
+        (
+            BodyEncoding::Json,
+            None,
+            "application/json",
+            r#"{"operation_id":"1"}"#,
+        ),
+        (
+            BodyEncoding::Json,
+            Some(BodyEncoding::Form),
+            "application/x-www-form-urlencoded",
+            "operation_id=1",
+        ),
+    ] {
+        let rsp = r#"{"error":"illegal_params"}"#;
+        let (addr, request) = serve_capturing(format!(
+            "HTTP/1.1 200 OK\r\ncontent-length: {}\r\n\r\n{rsp}",
+            rsp.len()
+        ))
+        .await;
+        let mut builder = Client::builder()
+            .addr(addr)
+            .token("token")
+            .body_encoding(encoding);
+        if let Some(override_encoding) = override_encoding {
+            builder = builder.endpoint_body_encoding("api/operation-details", override_encoding);
+        }
+        let _ = builder.build().operation_details("1".into()).await;
+
+        let request = request.await.unwrap();
+        assert!(
+            request.contains(&format!("content-type: {content_type}\r\n")),
+            "{request}"
+        );
+        assert!(request.ends_with(&format!("\r\n\r\n{body}")), "{request}");
+    }
+}
+
+#[tokio::test]
+async fn connect_timeout_fails_fast_on_unroutable_address() {
+    let client = Client::builder()
+        .addr("http://10.255.255.1")
+        .token("token")
+        .connect_timeout(Duration::from_millis(200))
+        .request_timeout(Duration::from_secs(30))
+        .build();
+
+    let started = std::time::Instant::now();
+    let err = client.account_info().await.unwrap_err();
+    assert!(started.elapsed() < Duration::from_secs(5), "{err:?}");
+    // Usually a connect timeout, though some networks reset such connections outright instead
+    let err = err
+        .downcast_ref::<reqwest::Error>()
+        .expect("transport error");
+    assert!(err.status().is_none(), "{err:?}");
+}
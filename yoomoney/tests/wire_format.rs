@@ -0,0 +1,110 @@
+use serde::{de::DeserializeOwned, Serialize};
+use std::fmt::Debug;
+use yoomoney::*;
+
+fn check<T>(value: T, wire: &str)
+where
+    T: Serialize + DeserializeOwned + Debug,
+{
+    let json = format!("\"{wire}\"");
+    assert_eq!(serde_json::to_string(&value).unwrap(), json, "{value:?}");
+    assert_eq!(
+        format!("{:?}", serde_json::from_str::<T>(&json).unwrap()),
+        format!("{value:?}")
+    );
+}
+
+#[test]
+fn access_scope() {
+    check(AccessScope::AccountInfo, "account-info");
+    check(AccessScope::OperationHistory, "operation-history");
+    check(AccessScope::PaymentP2P, "payment-p2p");
+}
+
+#[test]
+fn account_status() {
+    check(AccountStatus::Anonymous, "anonymous");
+    check(AccountStatus::Named, "named");
+    check(AccountStatus::Identified, "identified");
+}
+
+#[test]
+fn account_type() {
+    check(AccountType::Personal, "personal");
+    check(AccountType::Professional, "professional");
+}
+
+#[test]
+fn card_type() {
+    check(CardType::VISA, "VISA");
+    check(CardType::MasterCard, "MasterCard");
+    check(CardType::AmericanExpress, "AmericanExpress");
+    check(CardType::JCB, "JCB");
+}
+
+#[test]
+fn req_operation_type() {
+    check(ReqOperationType::Deposition, "deposition");
+    check(ReqOperationType::Payment, "payment");
+    check(
+        ReqOperationType::IncomingTransfersUnaccepted,
+        "incoming-transfers-unaccepted",
+    );
+}
+
+#[test]
+fn rsp_operation_type() {
+    check(RspOperationType::PaymentShop, "payment-shop");
+    check(RspOperationType::OutgoingTransfer, "outgoing-transfer");
+    check(RspOperationType::Deposition, "deposition");
+    check(RspOperationType::IncomingTransfer, "incoming-transfer");
+    check(
+        RspOperationType::IncomingTransferProtected,
+        "incoming-transfer-protected",
+    );
+}
+
+#[test]
+fn operation_status() {
+    check(OperationStatus::Success, "success");
+    check(OperationStatus::Refused, "refused");
+    check(OperationStatus::InProgress, "in_progress");
+}
+
+#[test]
+fn transfer_direction() {
+    check(TransferDirection::In, "in");
+    check(TransferDirection::Out, "out");
+}
+
+#[test]
+fn recipient_type() {
+    check(RecipientType::Account, "account");
+    check(RecipientType::Phone, "phone");
+    check(RecipientType::Email, "email");
+}
+
+#[test]
+fn process_payment_status() {
+    for (rsp, status) in [
+        (
+            serde_json::json!({ "status": "refused", "error": "x" }),
+            "refused",
+        ),
+        (
+            serde_json::json!({ "status": "in_progress", "next_retry": 5000 }),
+            "in_progress",
+        ),
+        (
+            serde_json::json!({ "status": "ext_auth_required" }),
+            "ext_auth_required",
+        ),
+        (
+            serde_json::json!({ "status": "account_blocked", "account_unblock_uri": "x" }),
+            "account_blocked",
+        ),
+    ] {
+        let parsed = serde_json::from_value::<ProcessPaymentResponse>(rsp).unwrap();
+        assert_eq!(serde_json::to_value(&parsed).unwrap()["status"], status);
+    }
+}